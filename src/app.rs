@@ -1,4 +1,9 @@
+use crate::warnings::RenderWarning;
 use crate::{PdfApp, ImgApp};
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 
 #[derive(Debug, Clone)]
 pub enum WkhtmlInput<'a> {
@@ -9,20 +14,144 @@ pub enum WkhtmlInput<'a> {
 
 #[derive(Debug, Clone)]
 pub enum WkhtmlError {
-    ServiceErr(String),
-    RenderingErr(String),
+    /// The tool is not installed, or does not answer `-V`.
+    BinaryNotFound {
+        cmd: String,
+        source: Option<Arc<io::Error>>,
+    },
+    /// The tool exists but its process could not be started.
+    Spawn { cmd: String, source: Arc<io::Error> },
+    /// A pipe, a temporary file or the child's status could not be read or written.
+    Io {
+        context: &'static str,
+        source: Arc<io::Error>,
+    },
+    /// The tool ran and failed, `code` is `None` when it was killed by a signal.
+    NonZeroExit {
+        code: Option<i32>,
+        stderr: String,
+        warnings: Vec<RenderWarning>,
+    },
+    InvalidOption { key: String, reason: String },
+    /// A `PdfDocument` that cannot be rendered, e.g. without any page.
+    InvalidDocument(String),
+    InvalidWorkDir {
+        path: PathBuf,
+        source: Option<Arc<io::Error>>,
+    },
+    /// The caller's writer failed while output was being copied into it.
+    Writer(Arc<io::Error>),
+    Timeout { timeout: Duration, stderr: String },
+    /// The `RenderPool` queue already holds `max_queue` renders.
+    QueueFull { max_queue: usize },
+    /// No `RenderPool` slot freed up within the queue timeout.
+    QueueTimeout { waited: Duration },
+    /// A batch item not rendered because an earlier one failed in fail-fast mode.
+    Skipped,
+    /// A file or URL input refused by the app's `SandboxPolicy`, or a file input
+    /// that is not a path, e.g. empty.
+    InputNotAllowed { input: String, reason: String },
+    /// A URL input refused by the app's `UrlPolicy`, or one starting with a dash.
+    UrlRejected { url: String, reason: String },
+    /// An output name that is not a plain file name, e.g. `../report`.
+    InvalidName { name: String, reason: String },
+}
+
+impl WkhtmlError {
+    pub(crate) fn io(context: &'static str, source: io::Error) -> Self {
+        WkhtmlError::Io {
+            context,
+            source: Arc::new(source),
+        }
+    }
+
+    pub(crate) fn invalid_option(key: &str, reason: impl Into<String>) -> Self {
+        WkhtmlError::InvalidOption {
+            key: key.to_string(),
+            reason: reason.into(),
+        }
+    }
 }
 
 impl std::fmt::Display for WkhtmlError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
-            WkhtmlError::ServiceErr(msg) => write!(f, "Service error: {}", msg),
-            WkhtmlError::RenderingErr(msg) => write!(f, "Rendering error: {}", msg),
+            WkhtmlError::BinaryNotFound { cmd, .. } => write!(
+                f,
+                "{} tool is not found, please install wkhtmltopdf or check the command",
+                cmd
+            ),
+            WkhtmlError::Spawn { cmd, source } => {
+                write!(f, "Failed to spawn {}: {}", cmd, source)
+            }
+            WkhtmlError::Io { context, source } => write!(f, "{}: {}", context, source),
+            WkhtmlError::NonZeroExit {
+                code,
+                stderr,
+                warnings,
+            } => {
+                match code {
+                    Some(code) => write!(f, "Rendering failed with exit code {}", code)?,
+                    None => write!(f, "Rendering was terminated by a signal")?,
+                }
+                // The parsed errors are much shorter than stderr and its progress output
+                let errors: Vec<String> = warnings
+                    .iter()
+                    .filter(|warning| warning.is_error())
+                    .map(ToString::to_string)
+                    .collect();
+                if errors.is_empty() {
+                    write!(f, ": {}", stderr.trim())
+                } else {
+                    write!(f, ": {}", errors.join("; "))
+                }
+            }
+            WkhtmlError::InvalidOption { key, reason } => {
+                write!(f, "Invalid option {}: {}", key, reason)
+            }
+            WkhtmlError::InvalidDocument(msg) => write!(f, "Invalid document: {}", msg),
+            WkhtmlError::InvalidWorkDir { path, source } => match source {
+                Some(source) => write!(f, "Invalid work dir {}: {}", path.display(), source),
+                None => write!(f, "Work dir {} is not found", path.display()),
+            },
+            WkhtmlError::Writer(source) => write!(f, "Failed to write output: {}", source),
+            WkhtmlError::Timeout { timeout, stderr } => {
+                write!(f, "Rendering timed out after {:?}: {}", timeout, stderr)
+            }
+            WkhtmlError::QueueFull { max_queue } => {
+                write!(f, "Render queue is full ({} waiting)", max_queue)
+            }
+            WkhtmlError::QueueTimeout { waited } => {
+                write!(f, "No render slot freed up after waiting {:?}", waited)
+            }
+            WkhtmlError::Skipped => write!(f, "Skipped after an earlier failure"),
+            WkhtmlError::InputNotAllowed { input, reason } => {
+                write!(f, "Input {} is not allowed: {}", input, reason)
+            }
+            WkhtmlError::UrlRejected { url, reason } => {
+                write!(f, "URL {} is rejected: {}", url, reason)
+            }
+            WkhtmlError::InvalidName { name, reason } => {
+                write!(f, "Invalid output name {:?}: {}", name, reason)
+            }
         }
     }
 }
 
-impl std::error::Error for WkhtmlError {}
+impl std::error::Error for WkhtmlError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            WkhtmlError::BinaryNotFound { source, .. }
+            | WkhtmlError::InvalidWorkDir { source, .. } => source
+                .as_deref()
+                .map(|e| e as &(dyn std::error::Error + 'static)),
+            WkhtmlError::Spawn { source, .. }
+            | WkhtmlError::Io { source, .. }
+            | WkhtmlError::Writer(source) => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct App {
@@ -0,0 +1,164 @@
+use crate::core::{kill_tree, redact, Core};
+use crate::output::{OutputData, OutputPath, RenderOutput};
+use crate::progress::StderrCollector;
+use crate::renderer::RenderRequest;
+use crate::{ImgApp, PdfApp, WkhtmlError, WkhtmlInput};
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::process::Output;
+use std::time::Instant;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+impl Core {
+    /// Async version of `Core::run`, built on `tokio::process`.
+    ///
+    /// Dropping the returned future kills the child process.
+    pub async fn run_async(
+        &self,
+        input: WkhtmlInput<'_>,
+        name: &str,
+        args: Vec<String>,
+    ) -> Result<PathBuf, WkhtmlError> {
+        Self::check_name(name)?;
+        let mut args = args;
+        let html = self.push_checked_input(&mut args, &input)?;
+        let out_path = self.get_out_path(name);
+        args.push(out_path.to_string_lossy().into_owned());
+        self.spawn_and_wait_async(args, html).await?;
+        Ok(out_path)
+    }
+
+    /// Async version of `Renderer::render_to_file`.
+    pub async fn render_to_file_async(
+        &self,
+        request: RenderRequest<'_>,
+        name: &str,
+    ) -> Result<RenderOutput, WkhtmlError> {
+        Self::check_name(name)?;
+        let mut temp_files = Vec::new();
+        let built = self.command_line(&request, &mut temp_files);
+        // Removes the temporary inputs even when the future is dropped
+        let _temp_inputs: Vec<OutputPath> = temp_files.into_iter().map(OutputPath::new).collect();
+        let (mut args, stdin) = built?;
+        // Removes partial output if the render fails
+        let out_path = OutputPath::new(self.get_out_path(name));
+        args.push(out_path.to_string_lossy().into_owned());
+        let started = Instant::now();
+        let output = self.spawn_and_wait_async(args.clone(), stdin).await?;
+        let data = OutputData::File(out_path);
+        let mut output = self.render_output(&args, started, output.status, &output.stderr, data);
+        output.command_line = redact(&output.command_line, &request.secrets);
+        Ok(output)
+    }
+
+    async fn spawn_and_wait_async(
+        &self,
+        args: Vec<String>,
+        html: Option<&str>,
+    ) -> Result<Output, WkhtmlError> {
+        let (args, html) = self.invocation(args, html)?;
+        let permit = match &self.pool {
+            Some(pool) => Some(pool.acquire_async().await?),
+            None => None,
+        };
+        let result = self.wait_for_child_async(args, html.as_deref()).await;
+        if let Some(permit) = permit {
+            permit.record(&result);
+        }
+        result
+    }
+
+    async fn wait_for_child_async(
+        &self,
+        args: Vec<String>,
+        html: Option<&str>,
+    ) -> Result<Output, WkhtmlError> {
+        let mut cmd = tokio::process::Command::from(self.command(args, html.is_some()));
+        cmd.kill_on_drop(true);
+        let mut child = cmd
+            .spawn()
+            .map_err(|e| Self::spawn_err(&self.wkhtmltox_cmd, e))?;
+        let pid = child.id();
+        let stdin = child.stdin.take();
+        let mut stdout = child.stdout.take().ok_or_else(|| {
+            WkhtmlError::io("Failed to open stdout", ErrorKind::BrokenPipe.into())
+        })?;
+        let mut stderr = child.stderr.take().ok_or_else(|| {
+            WkhtmlError::io("Failed to open stderr", ErrorKind::BrokenPipe.into())
+        })?;
+
+        let mut out = Vec::new();
+        let mut err = StderrCollector::new(self.progress.as_ref());
+        let render = async {
+            let write = async {
+                match (stdin, html) {
+                    (Some(mut stdin), Some(html)) => stdin.write_all(html.as_bytes()).await,
+                    (None, Some(_)) => Err(std::io::Error::other("Failed to open stdin")),
+                    // Dropping stdin closes it, so the child sees the end of its input
+                    _ => Ok(()),
+                }
+            };
+            let read_err = async {
+                let mut buf = [0; 8 * 1024];
+                loop {
+                    match stderr.read(&mut buf).await? {
+                        0 => return Ok(()),
+                        read => err.push(&buf[..read]),
+                    }
+                }
+            };
+            let (written, read_out, read_err): (_, _, std::io::Result<()>) =
+                tokio::join!(write, stdout.read_to_end(&mut out), read_err);
+            let read = read_out.and(read_err);
+            (written, read, child.wait().await)
+        };
+        let finished = match self.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, render).await.ok(),
+            None => Some(render.await),
+        };
+
+        let (written, read, status) = match finished {
+            Some(finished) => finished,
+            None => {
+                if let Some(pid) = pid {
+                    kill_tree(pid);
+                }
+                let _ = child.kill().await;
+                return Err(self.timeout_err(&err.finish()));
+            }
+        };
+        let status = status.map_err(|e| WkhtmlError::io("Failed to wait for child process", e))?;
+        read.map_err(|e| WkhtmlError::io("Failed to read output", e))?;
+        let output = Output {
+            status,
+            stdout: out,
+            stderr: err.finish(),
+        };
+        self.check_output(output, false, written)
+    }
+}
+
+impl PdfApp {
+    pub async fn run_async(
+        &self,
+        input: WkhtmlInput<'_>,
+        name: &str,
+    ) -> Result<RenderOutput, WkhtmlError> {
+        let name = format!("{}.pdf", name);
+        let request = self.options.to_request(input);
+        self.app.render_to_file_async(request, &name).await
+    }
+}
+
+impl ImgApp {
+    pub async fn run_async(
+        &self,
+        input: WkhtmlInput<'_>,
+        name: &str,
+    ) -> Result<RenderOutput, WkhtmlError> {
+        let name = format!("{}.{}", name, self.format);
+        let request = RenderRequest::new(self.options.to_args(&self.format)?, input)
+            .with_secrets(self.options.secrets());
+        self.app.render_to_file_async(request, &name).await
+    }
+}
@@ -0,0 +1,137 @@
+use crate::app::{WkhtmlError, WkhtmlInput};
+use crate::output::RenderOutput;
+use log::info;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+/// One document of a batch. `options` replaces the app's options for this
+/// document only.
+#[derive(Debug, Clone)]
+pub struct BatchItem<'a, O> {
+    pub input: WkhtmlInput<'a>,
+    pub name: String,
+    pub options: Option<O>,
+}
+
+impl<'a, O> BatchItem<'a, O> {
+    pub fn new(input: WkhtmlInput<'a>, name: &str) -> Self {
+        Self {
+            input,
+            name: name.to_string(),
+            options: None,
+        }
+    }
+
+    pub fn with_options(input: WkhtmlInput<'a>, name: &str, options: O) -> Self {
+        Self {
+            input,
+            name: name.to_string(),
+            options: Some(options),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BatchMode {
+    /// Renders every item whatever the failures.
+    #[default]
+    ContinueOnError,
+    /// Stops starting new items after the first failure, they are reported as
+    /// `WkhtmlError::Skipped`.
+    FailFast,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatchConfig {
+    /// Items rendered at the same time.
+    pub parallelism: usize,
+    pub mode: BatchMode,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self {
+            parallelism: thread::available_parallelism().map_or(1, |n| n.get()),
+            mode: BatchMode::default(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct BatchReport {
+    /// One result per item, in input order.
+    pub results: Vec<Result<RenderOutput, WkhtmlError>>,
+    pub succeeded: usize,
+    pub failed: usize,
+    /// Items not rendered because of an earlier failure in fail-fast mode.
+    pub skipped: usize,
+}
+
+impl BatchReport {
+    pub fn is_success(&self) -> bool {
+        self.failed == 0 && self.skipped == 0
+    }
+}
+
+/// Runs `render` over `items` on `config.parallelism` threads.
+pub(crate) fn run_batch<'a, O, F>(
+    items: impl IntoIterator<Item = BatchItem<'a, O>>,
+    config: &BatchConfig,
+    render: F,
+) -> BatchReport
+where
+    O: Sync,
+    F: Fn(&BatchItem<'a, O>) -> Result<RenderOutput, WkhtmlError> + Sync,
+{
+    let items: Vec<BatchItem<'a, O>> = items.into_iter().collect();
+    let results: Mutex<Vec<Option<Result<RenderOutput, WkhtmlError>>>> =
+        Mutex::new(items.iter().map(|_| None).collect());
+    let next = AtomicUsize::new(0);
+    let stop = AtomicBool::new(false);
+
+    thread::scope(|scope| {
+        for _ in 0..config.parallelism.clamp(1, items.len().max(1)) {
+            scope.spawn(|| loop {
+                if stop.load(Ordering::SeqCst) {
+                    break;
+                }
+                let index = next.fetch_add(1, Ordering::SeqCst);
+                let Some(item) = items.get(index) else {
+                    break;
+                };
+                let result = render(item);
+                if result.is_err() && config.mode == BatchMode::FailFast {
+                    stop.store(true, Ordering::SeqCst);
+                }
+                results.lock().unwrap_or_else(|e| e.into_inner())[index] = Some(result);
+            });
+        }
+    });
+
+    let results: Vec<_> = results
+        .into_inner()
+        .unwrap_or_else(|e| e.into_inner())
+        .into_iter()
+        .map(|result| result.unwrap_or(Err(WkhtmlError::Skipped)))
+        .collect();
+    let succeeded = results.iter().filter(|result| result.is_ok()).count();
+    let skipped = results
+        .iter()
+        .filter(|result| matches!(result, Err(WkhtmlError::Skipped)))
+        .count();
+    let failed = results.len() - succeeded - skipped;
+    info!(
+        "Batch of {} rendered: {} succeeded, {} failed, {} skipped",
+        results.len(),
+        succeeded,
+        failed,
+        skipped
+    );
+    BatchReport {
+        results,
+        succeeded,
+        failed,
+        skipped,
+    }
+}
@@ -1,35 +1,105 @@
+use crate::options::option_arity;
+use crate::output::{OutputData, OutputPath, RenderOutput};
+use crate::pool::RenderPool;
+use crate::progress::{read_stderr, ProgressHandler};
+use crate::renderer::{RenderRequest, Renderer};
+use crate::sandbox::SandboxPolicy;
+use crate::url_policy::UrlPolicy;
+use crate::warnings::{parse_warnings, RenderWarning};
 use crate::{WkhtmlError, WkhtmlInput};
 
 use self::uuid::Uuid;
-use log::{debug, error, info};
-use std::collections::HashMap;
-use std::path::PathBuf;
-use std::process::{Command, Output, Stdio};
-use std::{env, fs, io::Write};
+use log::{debug, error, info, warn};
+use std::borrow::Cow;
+use std::io::{ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Output, Stdio};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::{env, fs, thread};
 use uuid;
 
 const USE_STDIN_MARKER: &str = "-";
-const NO_WKHTMLTOPDF_ERR: &str = "wkhtmltopdf tool is not found. Please install it.";
+const USE_STDOUT_MARKER: &str = "-";
+pub(crate) const READ_ARGS_FROM_STDIN: &str = "read-args-from-stdin";
+/// Longest argument line the tool reads in one piece, without its line break:
+/// it reads stdin with `fgets` into a buffer of 20398 bytes, and the tail of a
+/// longer line would run as a job of its own.
+pub(crate) const MAX_ARGS_LINE: usize = 20_396;
+const REDACTED: &str = "<redacted>";
+/// A raw command line option, without its leading `--`, followed by its values.
+///
+/// Options such as `cookie` or `custom-header` take two values and may be given
+/// several times, so they are kept as an ordered list rather than a map.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawArg {
+    pub key: String,
+    pub values: Vec<String>,
+    /// Redacts the values from logs and `RenderOutput::command_line`.
+    pub secret: bool,
+}
+
+impl RawArg {
+    pub fn new(key: &str, values: &[&str]) -> Self {
+        Self {
+            key: key.to_string(),
+            values: values.iter().map(|v| v.to_string()).collect(),
+            secret: false,
+        }
+    }
+
+    /// An option whose values are secret, see `Core::set_secure_args` to also
+    /// keep them off the process' command line.
+    pub fn secret(key: &str, values: &[&str]) -> Self {
+        Self {
+            secret: true,
+            ..Self::new(key, values)
+        }
+    }
+
+    /// The values to hide: all of them for secret options, and those of the
+    /// well-known sensitive options such as `password` or a `cookie` value.
+    pub(crate) fn secret_values(&self) -> &[String] {
+        if self.secret {
+            return &self.values;
+        }
+        match self.key.as_str() {
+            "password" | "ssl-key-password" => &self.values,
+            // The name is kept, only the value is sensitive
+            "cookie" | "custom-header" => self.values.get(1..).unwrap_or_default(),
+            _ => &[],
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct Core {
     pub wkhtmltox_cmd: String,
     pub work_dir: PathBuf,
+    pub timeout: Option<Duration>,
+    pub progress: Option<ProgressHandler>,
+    pub pool: Option<RenderPool>,
+    pub secure_args: bool,
+    pub sandbox: Option<SandboxPolicy>,
+    pub url_policy: Option<UrlPolicy>,
 }
 
 impl Core {
     pub fn new(wkhtmltox_cmd: String) -> Result<Self, WkhtmlError> {
-        Self::bin_checks(&wkhtmltox_cmd).map_err(WkhtmlError::ServiceErr)?;
+        Self::bin_checks(&wkhtmltox_cmd)?;
         let work_dir = env::var("WKHTMLAPP_WORK_DIR");
         let work_dir = work_dir.unwrap_or_else(|_| Self::default_work_dir());
-        fs::create_dir_all(&work_dir).map_err(|e| {
-            WkhtmlError::ServiceErr(format!("Failed to create working directory, due to: {}", e))
-        })?;
-        let work_dir = Self::parse_dir(&work_dir)?;
+        let work_dir = Self::create_dir(&work_dir)?;
 
         Ok(Self {
             wkhtmltox_cmd,
             work_dir,
+            timeout: None,
+            progress: None,
+            pool: None,
+            secure_args: false,
+            sandbox: None,
+            url_policy: None,
         })
     }
 
@@ -43,37 +113,53 @@ impl Core {
         if path.is_dir() {
             Ok(path)
         } else {
-            Err(WkhtmlError::ServiceErr(format!(
-                "Directory {} is not found",
-                dir
-            )))
+            Err(WkhtmlError::InvalidWorkDir { path, source: None })
         }
     }
 
-    pub fn bin_checks(wkhtmltox_cmd: &str) -> Result<(), String> {
+    fn create_dir(dir: &str) -> Result<PathBuf, WkhtmlError> {
+        fs::create_dir_all(dir).map_err(|e| WkhtmlError::InvalidWorkDir {
+            path: PathBuf::from(dir),
+            source: Some(Arc::new(e)),
+        })?;
+        Self::parse_dir(dir)
+    }
+
+    pub fn bin_checks(wkhtmltox_cmd: &str) -> Result<(), WkhtmlError> {
         info!("Bootstrap check for {} tool", wkhtmltox_cmd);
         let status = Command::new(wkhtmltox_cmd)
             .arg("-V")
             .spawn()
-            .map_err(|e| format!("Failed to spawn child process: {}", e))
-            .and_then(|mut p| {
-                p.wait().map_err(|e| {
-                    format!("Failed to wait for {} tool , error: {}", wkhtmltox_cmd, e)
-                })
-            });
+            .map_err(|e| Self::spawn_err(wkhtmltox_cmd, e))?
+            .wait()
+            .map_err(|e| WkhtmlError::io("Failed to wait for child process", e))?;
 
-        status
-            .and_then(|s| {
-                if s.success() {
-                    Ok(())
-                } else {
-                    Err(NO_WKHTMLTOPDF_ERR.to_string())
-                }
-            })
-            .map_err(|e| {
-                error!("{:?}", e);
-                NO_WKHTMLTOPDF_ERR.to_string()
-            })
+        if status.success() {
+            Ok(())
+        } else {
+            let err = WkhtmlError::BinaryNotFound {
+                cmd: wkhtmltox_cmd.to_string(),
+                source: None,
+            };
+            error!("{}", err);
+            Err(err)
+        }
+    }
+
+    pub(crate) fn spawn_err(cmd: &str, e: std::io::Error) -> WkhtmlError {
+        let err = if e.kind() == ErrorKind::NotFound {
+            WkhtmlError::BinaryNotFound {
+                cmd: cmd.to_string(),
+                source: Some(Arc::new(e)),
+            }
+        } else {
+            WkhtmlError::Spawn {
+                cmd: cmd.to_string(),
+                source: Arc::new(e),
+            }
+        };
+        error!("{}", err);
+        err
     }
 
     pub fn get_debug() -> bool {
@@ -86,41 +172,113 @@ impl Core {
 
     pub fn depure(output: &Output) {
         debug!("status: {}", output.status);
-        debug!("stdout: {}", String::from_utf8_lossy(&output.stdout));
+        debug!("stdout: {} bytes", output.stdout.len());
         debug!("stderr: {}", String::from_utf8_lossy(&output.stderr));
     }
 
     pub fn set_work_dir(&mut self, work_dir: &str) -> Result<&mut Self, WkhtmlError> {
-        fs::create_dir_all(work_dir).map_err(|e| {
-            WkhtmlError::ServiceErr(format!("Failed to create working directory, due to: {}", e))
-        })?;
-        self.work_dir = Self::parse_dir(work_dir)?;
+        self.work_dir = Self::create_dir(work_dir)?;
         Ok(self)
     }
 
+    /// Kills renders that run longer than `timeout`, `None` waits forever.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) -> &mut Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Reports the progress of every render to `progress`, `None` disables it.
+    pub fn set_progress(&mut self, progress: Option<ProgressHandler>) -> &mut Self {
+        self.progress = progress;
+        self
+    }
+
+    /// Makes every render wait for a slot of `pool`, `None` removes the limit.
+    pub fn set_pool(&mut self, pool: Option<RenderPool>) -> &mut Self {
+        self.pool = pool;
+        self
+    }
+
+    /// Writes the arguments to the tool's stdin with `--read-args-from-stdin`
+    /// instead of passing them on its command line, where any user of the host
+    /// can read them. HTML inputs then go through files in the work dir.
+    ///
+    /// wkhtmltoimage has no such option, renders with it fail with
+    /// `WkhtmlError::InvalidOption`.
+    pub fn set_secure_args(&mut self, secure_args: bool) -> &mut Self {
+        self.secure_args = secure_args;
+        self
+    }
+
+    /// Restricts every render to `sandbox`, `None` lifts the restrictions.
+    pub fn set_sandbox(&mut self, sandbox: Option<SandboxPolicy>) -> &mut Self {
+        self.sandbox = sandbox;
+        self
+    }
+
+    /// Checks every URL input against `url_policy`, `None` accepts any URL.
+    pub fn set_url_policy(&mut self, url_policy: Option<UrlPolicy>) -> &mut Self {
+        self.url_policy = url_policy;
+        self
+    }
+
+    /// A new path in the work dir for a file called `name`. Path separators in
+    /// `name` are replaced, so the file can't end up outside of the work dir.
     pub fn get_out_path(&self, name: &str) -> PathBuf {
+        let name = name.replace(['/', '\\', '\0'], "_");
         let temp_name = format!("{}-{}", Uuid::new_v4(), name);
         self.work_dir.join(temp_name)
     }
 
-    pub fn build_args(options: &HashMap<String, String>) -> Vec<String> {
+    /// Refuses output names that are not a plain file name.
+    pub fn check_name(name: &str) -> Result<(), WkhtmlError> {
+        let reason = if name.is_empty() {
+            "the name is empty"
+        } else if matches!(name, "." | "..") {
+            "the name is a directory"
+        } else if name.contains(['/', '\\']) {
+            "the name contains a path separator"
+        } else if name.chars().any(char::is_control) {
+            "the name contains control characters"
+        } else {
+            return Ok(());
+        };
+        Err(WkhtmlError::InvalidName {
+            name: name.to_string(),
+            reason: reason.to_string(),
+        })
+    }
+
+    /// Builds the command line for raw options, sorted by name so the same
+    /// options always produce the same command line. Repeated options keep
+    /// the order they were added in.
+    pub fn build_args(options: &[RawArg]) -> Vec<String> {
+        let mut options: Vec<&RawArg> = options.iter().collect();
+        options.sort_by(|a, b| a.key.cmp(&b.key));
         let mut args = Vec::new();
-        for (key, v) in options {
-            if *v == "false" {
-                continue;
-            }
-            if *v == "true" {
-                if *key == "toc" || *key == "cover" {
-                    args.push(key.to_string());
-                } else {
-                    args.push(format!("--{}", key));
+        for option in options {
+            let key = &option.key;
+            match option.values.as_slice() {
+                [v] if v == "false" => continue,
+                [] => args.push(format!("--{}", key)),
+                [v] if v == "true" => {
+                    if *key == "toc" || *key == "cover" {
+                        args.push(key.to_string());
+                    } else {
+                        args.push(format!("--{}", key));
+                    }
                 }
-            } else {
-                if *key == "toc" || *key == "cover" {
-                    args.push(key.to_string());
-                } else {
-                    args.push(format!("--{}", key));
-                    args.push(v.to_string());
+                values => {
+                    if *key == "toc" {
+                        args.push(key.to_string());
+                    } else if *key == "cover" {
+                        // The cover object is followed by its input
+                        args.push(key.to_string());
+                        args.extend(values.iter().map(|value| escape_path(value)));
+                    } else {
+                        args.push(format!("--{}", key));
+                        args.extend(values.iter().cloned());
+                    }
                 }
             }
         }
@@ -140,127 +298,778 @@ impl Core {
         }
     }
 
+    /// Renders to the child's stdout and returns the output bytes, without
+    /// touching the work dir.
+    pub fn run_to_bytes(
+        &self,
+        input: WkhtmlInput,
+        args: Vec<String>,
+    ) -> Result<Vec<u8>, WkhtmlError> {
+        let mut args = args;
+        let stdin = self.push_checked_input(&mut args, &input)?;
+        args.push(USE_STDOUT_MARKER.to_string());
+        let output = self.spawn_and_wait(args, stdin)?;
+        Ok(output.stdout)
+    }
+
     pub fn run_with_url(
         &self,
         url: &str,
         name: &str,
         args: Vec<String>,
     ) -> Result<PathBuf, WkhtmlError> {
-        let out_path = self.get_out_path(name);
-        let mut cmd = Command::new(&self.wkhtmltox_cmd);
-        cmd.args(args)
-            .arg(url)
-            .arg(&out_path)
-            .stdout(Stdio::piped());
+        Self::check_name(name)?;
+        let mut args = args;
+        self.push_checked_input(&mut args, &WkhtmlInput::Url(url))?;
+        self.execute(args, None, self.get_out_path(name))
+    }
 
-        if !Self::get_debug() {
-            cmd.stderr(Stdio::piped());
-        }
+    pub fn run_with_file(
+        &self,
+        file_path: &str,
+        name: &str,
+        args: Vec<String>,
+    ) -> Result<PathBuf, WkhtmlError> {
+        Self::check_name(name)?;
+        let mut args = args;
+        self.push_checked_input(&mut args, &WkhtmlInput::File(file_path))?;
+        self.execute(args, None, self.get_out_path(name))
+    }
 
-        let child = cmd.spawn().map_err(|e| {
-            WkhtmlError::RenderingErr(format!("Failed to spawn child process: {}", e))
-        })?;
+    pub fn run_with_html(
+        &self,
+        html: &str,
+        name: &str,
+        args: Vec<String>,
+    ) -> Result<PathBuf, WkhtmlError> {
+        Self::check_name(name)?;
+        let mut args = args;
+        let stdin = self.push_checked_input(&mut args, &WkhtmlInput::Html(html))?;
+        self.execute(args, stdin, self.get_out_path(name))
+    }
 
-        let output = child.wait_with_output().map_err(|e| {
-            WkhtmlError::RenderingErr(format!("Failed to spawn child process: {}", e))
-        })?;
+    /// Appends the input to `args`, returning the HTML to write to stdin if any.
+    pub fn push_input<'a>(args: &mut Vec<String>, input: &WkhtmlInput<'a>) -> Option<&'a str> {
+        match input {
+            WkhtmlInput::File(path) => {
+                args.push(escape_path(path));
+                None
+            }
+            WkhtmlInput::Url(url) => {
+                args.push(url.to_string());
+                None
+            }
+            WkhtmlInput::Html(html) => {
+                args.push(USE_STDIN_MARKER.to_string());
+                Some(html)
+            }
+        }
+    }
 
-        #[cfg(debug_assertions)]
-        Self::depure(&output);
+    /// Like `push_input`, checking the input against the sandbox first and
+    /// appending its options before it.
+    pub(crate) fn push_checked_input<'a>(
+        &self,
+        args: &mut Vec<String>,
+        input: &WkhtmlInput<'a>,
+    ) -> Result<Option<&'a str>, WkhtmlError> {
+        self.check_raw_objects(args)?;
+        self.check_input(input)?;
+        args.extend(self.sandbox_args());
+        Ok(Self::push_input(args, input))
+    }
 
-        if output.status.success() {
-            Ok(out_path)
-        } else {
-            Err(WkhtmlError::RenderingErr(format!(
-                "Failed to render, error: {}",
-                String::from_utf8_lossy(&output.stderr)
-            )))
+    /// Refuses malformed inputs, inputs outside the sandbox's allowlists and URLs
+    /// the URL policy rejects.
+    pub(crate) fn check_input(&self, input: &WkhtmlInput) -> Result<(), WkhtmlError> {
+        match input {
+            WkhtmlInput::File(path) if path.is_empty() || path.chars().any(char::is_control) => {
+                return Err(WkhtmlError::InputNotAllowed {
+                    input: path.to_string(),
+                    reason: "not a file path".to_string(),
+                });
+            }
+            // Would be read as an option, a URL starts with its scheme
+            WkhtmlInput::Url(url) if url.starts_with('-') || url.chars().any(char::is_control) => {
+                return Err(WkhtmlError::UrlRejected {
+                    url: url.to_string(),
+                    reason: "not a URL".to_string(),
+                });
+            }
+            _ => {}
+        }
+        if let (Some(url_policy), WkhtmlInput::Url(url)) = (&self.url_policy, input) {
+            url_policy.check(url)?;
+        }
+        match &self.sandbox {
+            Some(sandbox) => sandbox.check_input(input),
+            None => Ok(()),
         }
     }
 
-    pub fn run_with_file(
+    /// With a sandbox or a URL policy, refuses object keywords in a list of
+    /// options: their inputs would not be checked, and the sandbox options
+    /// following them would only apply to that object. Option values such as
+    /// `--title cover` are skipped.
+    fn check_raw_objects(&self, args: &[String]) -> Result<(), WkhtmlError> {
+        if self.sandbox.is_none() && self.url_policy.is_none() {
+            return Ok(());
+        }
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if let Some(key) = arg.strip_prefix("--") {
+                for _ in 0..option_arity(key) {
+                    args.next();
+                }
+            } else if matches!(arg.as_str(), "cover" | "toc" | "page") {
+                return Err(WkhtmlError::invalid_option(
+                    arg,
+                    "objects must be passed as RenderObjects with a sandbox or a URL policy",
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn sandbox_args(&self) -> Vec<String> {
+        self.sandbox
+            .as_ref()
+            .map(SandboxPolicy::args)
+            .unwrap_or_default()
+    }
+
+    /// Writes `html` to a file in the work dir, for inputs that can't be piped
+    /// through stdin. The caller is responsible for removing it.
+    pub fn write_temp_html(&self, html: &str) -> Result<PathBuf, WkhtmlError> {
+        let path = self.get_out_path("input.html");
+        fs::write(&path, html)
+            .map_err(|e| WkhtmlError::io("Failed to write temporary input", e))?;
+        Ok(path)
+    }
+
+    /// Runs the tool with a complete argument list (options and inputs), writing
+    /// `stdin` to the child when given, and appends `out_path` as the output.
+    pub fn execute(
         &self,
-        file_path: &str,
-        name: &str,
         args: Vec<String>,
+        stdin: Option<&str>,
+        out_path: PathBuf,
     ) -> Result<PathBuf, WkhtmlError> {
-        let out_path = self.get_out_path(name);
-        let mut cmd = Command::new(&self.wkhtmltox_cmd);
-        cmd.args(args)
-            .arg(file_path)
-            .arg(&out_path)
-            .stdout(Stdio::piped());
+        let mut args = args;
+        args.push(out_path.to_string_lossy().into_owned());
+        self.spawn_and_wait(args, stdin)?;
+        Ok(out_path)
+    }
 
-        if !Self::get_debug() {
-            cmd.stderr(Stdio::piped());
+    /// Renders to the child's stdout and copies it into `writer` as it arrives,
+    /// returning the number of bytes written.
+    pub fn run_to_writer(
+        &self,
+        input: WkhtmlInput,
+        args: Vec<String>,
+        writer: &mut dyn Write,
+    ) -> Result<u64, WkhtmlError> {
+        let mut args = args;
+        let html = self.push_checked_input(&mut args, &input)?;
+        args.push(USE_STDOUT_MARKER.to_string());
+        Ok(self.stream(args, html, writer)?.size)
+    }
+
+    /// Builds the argument list of a request, without the output. The first HTML
+    /// input is returned to be written to stdin, the others are written to files
+    /// in the work dir whose paths are pushed to `temp_files`.
+    pub fn command_line<'r>(
+        &self,
+        request: &RenderRequest<'r>,
+        temp_files: &mut Vec<PathBuf>,
+    ) -> Result<(Vec<String>, Option<&'r str>), WkhtmlError> {
+        self.build_command_line(request, temp_files, self.secure_args)
+    }
+
+    /// Like `command_line`, writing every HTML input to a file when `html_files`
+    /// is set.
+    pub(crate) fn build_command_line<'r>(
+        &self,
+        request: &RenderRequest<'r>,
+        temp_files: &mut Vec<PathBuf>,
+        html_files: bool,
+    ) -> Result<(Vec<String>, Option<&'r str>), WkhtmlError> {
+        self.check_raw_objects(&request.args)?;
+        let mut args = request.args.clone();
+        args.extend(self.sandbox_args());
+        let mut stdin = None;
+        for object in &request.objects {
+            if let Some(input) = &object.input {
+                self.check_input(input)?;
+            }
+            args.extend(object.keyword.map(str::to_string));
+            match &object.input {
+                // Only one object can be read from stdin, the others go through the work
+                // dir, as do all of them when stdin carries the arguments
+                Some(WkhtmlInput::Html(html)) if stdin.is_some() || html_files => {
+                    let path = self.write_temp_html(html)?;
+                    args.push(path.to_string_lossy().into_owned());
+                    temp_files.push(path);
+                }
+                Some(input) => {
+                    if let Some(html) = Self::push_input(&mut args, input) {
+                        stdin = Some(html);
+                    }
+                }
+                None => {}
+            }
+            args.extend(object.args.iter().cloned());
+            // Page options of an object override the ones given before it
+            if matches!(object.keyword, Some("page" | "cover")) {
+                args.extend(self.sandbox_args());
+            }
         }
+        Ok((args, stdin))
+    }
 
-        let child = cmd.spawn().map_err(|e| {
-            WkhtmlError::RenderingErr(format!("Failed to spawn child process: {}", e))
-        })?;
+    /// Runs `f` with the command line of `request`, removing temporary inputs
+    /// afterwards and the request's secrets from the reported command line.
+    pub(crate) fn with_command_line(
+        &self,
+        request: &RenderRequest,
+        f: impl FnOnce(Vec<String>, Option<&str>) -> Result<RenderOutput, WkhtmlError>,
+    ) -> Result<RenderOutput, WkhtmlError> {
+        let mut temp_files = Vec::new();
+        let result = self
+            .command_line(request, &mut temp_files)
+            .and_then(|(args, stdin)| {
+                debug!("args: {}", redact(&args, &request.secrets).join(" "));
+                f(args, stdin)
+            });
+        for path in temp_files {
+            let _ = fs::remove_file(path);
+        }
+        result.map(|mut output| {
+            output.command_line = redact(&output.command_line, &request.secrets);
+            output
+        })
+    }
 
-        let output = child.wait_with_output().map_err(|e| {
-            WkhtmlError::RenderingErr(format!("Failed to spawn child process: {}", e))
-        })?;
+    /// The arguments and stdin the tool is actually run with: in secure mode the
+    /// arguments are moved to stdin, see `set_secure_args`.
+    pub(crate) fn invocation<'h>(
+        &self,
+        args: Vec<String>,
+        stdin: Option<&'h str>,
+    ) -> Result<(Vec<String>, Option<Cow<'h, str>>), WkhtmlError> {
+        if !self.secure_args {
+            return Ok((args, stdin.map(Cow::Borrowed)));
+        }
+        let tool = Path::new(&self.wkhtmltox_cmd).file_stem();
+        if tool.is_some_and(|tool| tool.to_string_lossy().starts_with("wkhtmltoimage")) {
+            return Err(WkhtmlError::invalid_option(
+                READ_ARGS_FROM_STDIN,
+                "only supported by wkhtmltopdf",
+            ));
+        }
+        if stdin.is_some() {
+            return Err(WkhtmlError::invalid_option(
+                READ_ARGS_FROM_STDIN,
+                "stdin carries the arguments, HTML has to be passed in a file",
+            ));
+        }
+        let line = format!("{}\n", args_line(&args)?);
+        Ok((
+            vec![format!("--{}", READ_ARGS_FROM_STDIN)],
+            Some(Cow::Owned(line)),
+        ))
+    }
 
-        #[cfg(debug_assertions)]
-        Self::depure(&output);
+    fn stream(
+        &self,
+        args: Vec<String>,
+        html: Option<&str>,
+        writer: &mut dyn Write,
+    ) -> Result<RenderOutput, WkhtmlError> {
+        let (args, html) = self.invocation(args, html)?;
+        self.pooled(|| self.stream_child(args, html.as_deref(), writer))
+    }
 
-        if output.status.success() {
-            Ok(out_path)
-        } else {
-            Err(WkhtmlError::RenderingErr(format!(
-                "Failed to render, error: {}",
-                String::from_utf8_lossy(&output.stderr)
-            )))
+    /// Runs `render` once the pool, if any, has a free slot for it.
+    fn pooled<T>(&self, render: impl FnOnce() -> Result<T, WkhtmlError>) -> Result<T, WkhtmlError> {
+        let permit = self.pool.as_ref().map(RenderPool::acquire).transpose()?;
+        let result = render();
+        if let Some(permit) = permit {
+            permit.record(&result);
         }
+        result
     }
 
-    pub fn run_with_html(
+    fn stream_child(
         &self,
-        html: &str,
-        name: &str,
         args: Vec<String>,
-    ) -> Result<PathBuf, WkhtmlError> {
-        let out_path = self.get_out_path(name);
+        html: Option<&str>,
+        writer: &mut dyn Write,
+    ) -> Result<RenderOutput, WkhtmlError> {
+        let started = Instant::now();
+        let mut child = self.spawn(args.clone(), html.is_some())?;
+        let watchdog = self.timeout.map(|timeout| Watchdog::start(&child, timeout));
+        let stdin = child.stdin.take();
+        let stderr = child.stderr.take();
+        let mut stdout = child.stdout.take().ok_or_else(|| {
+            WkhtmlError::io("Failed to open stdout", ErrorKind::BrokenPipe.into())
+        })?;
+
+        thread::scope(|scope| {
+            // stdin and stderr are handled on their own threads so the child never
+            // blocks on a full pipe while we are busy copying stdout
+            let stdin_writer = scope.spawn(move || match (stdin, html) {
+                (Some(mut stdin), Some(html)) => stdin.write_all(html.as_bytes()),
+                _ => Ok(()),
+            });
+            let progress = self.progress.as_ref();
+            let stderr_reader = scope.spawn(move || match stderr {
+                Some(stderr) => read_stderr(stderr, progress),
+                None => Vec::new(),
+            });
+
+            let copied = Self::copy_output(&mut stdout, writer);
+            if copied.is_err() {
+                let _ = child.kill();
+            }
+            drop(stdout);
+            wait_exited(&child);
+            if let Some(watchdog) = &watchdog {
+                watchdog.settle();
+            }
+            let status = child.wait();
+            let timed_out = watchdog.is_some_and(Watchdog::finish);
+            let written = stdin_writer.join();
+            let stderr = stderr_reader.join().unwrap_or_default();
+
+            if timed_out {
+                return Err(self.timeout_err(&stderr));
+            }
+            let copied = copied?;
+            let status =
+                status.map_err(|e| WkhtmlError::io("Failed to wait for child process", e))?;
+            debug!("status: {}", status);
+            debug!("stderr: {}", String::from_utf8_lossy(&stderr));
+            if !status.success() {
+                return Err(Self::exit_err(status, &stderr));
+            }
+            match written {
+                Ok(Ok(())) => {
+                    let mut output =
+                        self.render_output(&args, started, status, &stderr, OutputData::Written);
+                    output.size = copied;
+                    Ok(output)
+                }
+                Ok(Err(e)) => Err(WkhtmlError::io("Failed to write to stdin", e)),
+                Err(_) => Err(WkhtmlError::io(
+                    "Failed to write to stdin",
+                    std::io::Error::other("stdin writer panicked"),
+                )),
+            }
+        })
+    }
+
+    fn copy_output(reader: &mut impl Read, writer: &mut dyn Write) -> Result<u64, WkhtmlError> {
+        let mut buf = vec![0; 64 * 1024];
+        let mut total = 0;
+        loop {
+            let read = match reader.read(&mut buf) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(WkhtmlError::io("Failed to read output", e)),
+            };
+            writer
+                .write_all(&buf[..read])
+                .map_err(|e| WkhtmlError::Writer(Arc::new(e)))?;
+            total += read as u64;
+        }
+        writer
+            .flush()
+            .map_err(|e| WkhtmlError::Writer(Arc::new(e)))?;
+        Ok(total)
+    }
+
+    fn spawn(&self, args: Vec<String>, stdin: bool) -> Result<Child, WkhtmlError> {
+        self.command(args, stdin)
+            .spawn()
+            .map_err(|e| Self::spawn_err(&self.wkhtmltox_cmd, e))
+    }
+
+    pub(crate) fn command(&self, args: Vec<String>, stdin: bool) -> Command {
         let mut cmd = Command::new(&self.wkhtmltox_cmd);
-        cmd.args(args)
-            .arg(USE_STDIN_MARKER)
-            .arg(&out_path)
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped());
+        cmd.args(args).stdout(Stdio::piped());
 
-        if !Self::get_debug() {
-            cmd.stderr(Stdio::piped());
+        if stdin {
+            cmd.stdin(Stdio::piped());
         }
 
-        let mut child = cmd.spawn().map_err(|e| {
-            WkhtmlError::RenderingErr(format!("Failed to spawn child process: {}", e))
-        })?;
+        // stderr is always captured so it can be reported, it is logged in debug mode
+        cmd.stderr(Stdio::piped());
 
-        let stdin = child
-            .stdin
-            .as_mut()
-            .ok_or_else(|| WkhtmlError::RenderingErr("Failed to open stdin".to_string()))?;
+        // With a timeout the child gets its own process group, so that everything
+        // it started can be killed together
+        #[cfg(unix)]
+        if self.timeout.is_some() {
+            use std::os::unix::process::CommandExt;
+            cmd.process_group(0);
+        }
 
-        stdin
-            .write_all(html.as_bytes())
-            .map_err(|e| WkhtmlError::RenderingErr(format!("Failed to write to stdin: {}", e)))?;
+        cmd
+    }
 
-        let output = child.wait_with_output().map_err(|e| {
-            WkhtmlError::RenderingErr(format!("Failed to wait for child process: {}", e))
-        })?;
+    fn spawn_and_wait(
+        &self,
+        args: Vec<String>,
+        stdin: Option<&str>,
+    ) -> Result<Output, WkhtmlError> {
+        let (args, stdin) = self.invocation(args, stdin)?;
+        self.pooled(|| self.wait_for_child(args, stdin.as_deref()))
+    }
 
-        #[cfg(debug_assertions)]
-        Self::depure(&output);
+    fn wait_for_child(
+        &self,
+        args: Vec<String>,
+        stdin: Option<&str>,
+    ) -> Result<Output, WkhtmlError> {
+        let mut child = self.spawn(args, stdin.is_some())?;
+        let watchdog = self.timeout.map(|timeout| Watchdog::start(&child, timeout));
+        let pipe = child.stdin.take();
+        let stdout = child.stdout.take();
+        let stderr = child.stderr.take();
 
-        if output.status.success() {
-            Ok(out_path)
-        } else {
-            Err(WkhtmlError::RenderingErr(format!(
-                "Failed to render, error: {}",
-                String::from_utf8_lossy(&output.stderr)
-            )))
+        let (output, written) = thread::scope(|scope| {
+            // The input is fed on its own thread while stdout and stderr are drained,
+            // otherwise a child filling its output pipes before reading all of its
+            // input would block both processes
+            let feeder = stdin.map(|html| {
+                scope.spawn(move || match pipe {
+                    Some(mut pipe) => pipe.write_all(html.as_bytes()),
+                    None => Err(std::io::Error::other("Failed to open stdin")),
+                })
+            });
+            let stdout_reader = scope.spawn(move || {
+                let mut out = Vec::new();
+                match stdout {
+                    Some(mut stdout) => stdout.read_to_end(&mut out).map(|_| out),
+                    None => Ok(out),
+                }
+            });
+            // Progress updates are reported while the child runs
+            let stderr_reader = scope.spawn(|| match stderr {
+                Some(stderr) => read_stderr(stderr, self.progress.as_ref()),
+                None => Vec::new(),
+            });
+            wait_exited(&child);
+            if let Some(watchdog) = &watchdog {
+                watchdog.settle();
+            }
+            let output = child.wait().and_then(|status| {
+                let stdout = stdout_reader
+                    .join()
+                    .unwrap_or_else(|_| Err(std::io::Error::other("stdout reader panicked")))?;
+                Ok(Output {
+                    status,
+                    stdout,
+                    stderr: stderr_reader.join().unwrap_or_default(),
+                })
+            });
+            let written = match feeder.map(|feeder| feeder.join()) {
+                Some(Ok(written)) => written,
+                Some(Err(_)) => Err(std::io::Error::other("stdin writer panicked")),
+                None => Ok(()),
+            };
+            (output, written)
+        });
+        let timed_out = watchdog.is_some_and(Watchdog::finish);
+        let output = output.map_err(|e| WkhtmlError::io("Failed to wait for child process", e))?;
+        self.check_output(output, timed_out, written)
+    }
+
+    /// Turns a finished child into the render result: timeouts first, then the
+    /// exit status, then a failure to feed stdin.
+    pub(crate) fn check_output(
+        &self,
+        output: Output,
+        timed_out: bool,
+        written: std::io::Result<()>,
+    ) -> Result<Output, WkhtmlError> {
+        if Self::get_debug() {
+            Self::depure(&output);
+        }
+
+        if timed_out {
+            return Err(self.timeout_err(&output.stderr));
+        }
+        if !output.status.success() {
+            return Err(Self::exit_err(output.status, &output.stderr));
+        }
+        written.map_err(|e| WkhtmlError::io("Failed to write to stdin", e))?;
+        Ok(output)
+    }
+
+    /// Describes a successful render of `args` started at `started`.
+    pub(crate) fn render_output(
+        &self,
+        args: &[String],
+        started: Instant,
+        status: ExitStatus,
+        stderr: &[u8],
+        data: OutputData,
+    ) -> RenderOutput {
+        let mut command_line = vec![self.wkhtmltox_cmd.clone()];
+        command_line.extend(args.iter().cloned());
+        let mut output = RenderOutput {
+            data,
+            size: 0,
+            duration: started.elapsed(),
+            exit_code: status.code(),
+            warnings: Self::warnings(stderr),
+            command_line,
+            page_count: None,
+        };
+        output.measure();
+        output
+    }
+
+    fn exit_err(status: ExitStatus, stderr: &[u8]) -> WkhtmlError {
+        WkhtmlError::NonZeroExit {
+            code: status.code(),
+            stderr: String::from_utf8_lossy(stderr).into_owned(),
+            warnings: Self::warnings(stderr),
+        }
+    }
+
+    fn warnings(stderr: &[u8]) -> Vec<RenderWarning> {
+        let warnings = parse_warnings(&String::from_utf8_lossy(stderr));
+        for warning in &warnings {
+            warn!("{}", warning);
         }
+        warnings
+    }
+
+    pub(crate) fn timeout_err(&self, stderr: &[u8]) -> WkhtmlError {
+        WkhtmlError::Timeout {
+            timeout: self.timeout.unwrap_or_default(),
+            stderr: String::from_utf8_lossy(stderr).into_owned(),
+        }
+    }
+}
+
+impl Renderer for Core {
+    fn render_to_file(
+        &self,
+        request: RenderRequest<'_>,
+        name: &str,
+    ) -> Result<RenderOutput, WkhtmlError> {
+        Self::check_name(name)?;
+        self.with_command_line(&request, |mut args, stdin| {
+            // Removes partial output if the render fails
+            let out_path = OutputPath::new(self.get_out_path(name));
+            args.push(out_path.to_string_lossy().into_owned());
+            let started = Instant::now();
+            let output = self.spawn_and_wait(args.clone(), stdin)?;
+            let data = OutputData::File(out_path);
+            Ok(self.render_output(&args, started, output.status, &output.stderr, data))
+        })
     }
+
+    fn render_to_path(
+        &self,
+        request: RenderRequest<'_>,
+        path: &Path,
+    ) -> Result<RenderOutput, WkhtmlError> {
+        self.with_command_line(&request, |mut args, stdin| {
+            // Rendered next to the destination so the rename stays on one file
+            // system, keeping the extension the tool may look at
+            let file_name = path.file_name().ok_or_else(|| {
+                WkhtmlError::io(
+                    "Invalid output path",
+                    std::io::Error::new(ErrorKind::InvalidInput, "the path has no file name"),
+                )
+            })?;
+            let temp_name = format!(".{}-{}", Uuid::new_v4(), file_name.to_string_lossy());
+            let temp_path = OutputPath::new(path.with_file_name(temp_name));
+            args.push(temp_path.to_string_lossy().into_owned());
+            let started = Instant::now();
+            let output = self.spawn_and_wait(args.clone(), stdin)?;
+            fs::rename(&temp_path, path)
+                .map_err(|e| WkhtmlError::io("Failed to move output into place", e))?;
+            temp_path.keep();
+            let data = OutputData::Path(path.to_path_buf());
+            Ok(self.render_output(&args, started, output.status, &output.stderr, data))
+        })
+    }
+
+    fn render_to_writer(
+        &self,
+        request: RenderRequest<'_>,
+        writer: &mut dyn Write,
+    ) -> Result<RenderOutput, WkhtmlError> {
+        self.with_command_line(&request, |mut args, stdin| {
+            args.push(USE_STDOUT_MARKER.to_string());
+            self.stream(args, stdin, writer)
+        })
+    }
+
+    fn render_to_bytes(&self, request: RenderRequest<'_>) -> Result<RenderOutput, WkhtmlError> {
+        self.with_command_line(&request, |mut args, stdin| {
+            args.push(USE_STDOUT_MARKER.to_string());
+            let started = Instant::now();
+            let output = self.spawn_and_wait(args.clone(), stdin)?;
+            let data = OutputData::Bytes(output.stdout);
+            Ok(self.render_output(&args, started, output.status, &output.stderr, data))
+        })
+    }
+}
+
+/// Kills a child process, and the processes it started, once its timeout expires.
+///
+/// The child must be settled once it exited and before it is reaped: the kill
+/// and the settling are exclusive, so the process group is never signalled
+/// after its PID may have been reused.
+struct Watchdog {
+    done: mpsc::Sender<()>,
+    handle: thread::JoinHandle<bool>,
+    settled: Arc<Mutex<bool>>,
+}
+
+impl Watchdog {
+    fn start(child: &Child, timeout: Duration) -> Self {
+        let pid = child.id();
+        let (done, expired) = mpsc::channel();
+        let settled = Arc::new(Mutex::new(false));
+        let watched = settled.clone();
+        let handle = thread::spawn(move || match expired.recv_timeout(timeout) {
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                let mut settled = watched.lock().unwrap_or_else(|e| e.into_inner());
+                if *settled {
+                    return false;
+                }
+                error!("Render exceeded its timeout of {:?}, killing it", timeout);
+                kill_tree(pid);
+                *settled = true;
+                true
+            }
+            _ => false,
+        });
+        Self {
+            done,
+            handle,
+            settled,
+        }
+    }
+
+    /// Marks the child as exited on its own, unless it was already killed.
+    fn settle(&self) {
+        *self.settled.lock().unwrap_or_else(|e| e.into_inner()) = true;
+    }
+
+    /// Stops watching the child, returning whether it was killed.
+    fn finish(self) -> bool {
+        let _ = self.done.send(());
+        self.handle.join().unwrap_or(false)
+    }
+}
+
+/// Blocks until the child exited, without reaping it, so that its PID and
+/// process group stay reserved until `Child::wait`.
+#[cfg(unix)]
+fn wait_exited(child: &Child) {
+    loop {
+        let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+        let ret = unsafe {
+            libc::waitid(
+                libc::P_PID,
+                child.id() as libc::id_t,
+                &mut info,
+                libc::WEXITED | libc::WNOWAIT,
+            )
+        };
+        if ret == 0 || std::io::Error::last_os_error().kind() != ErrorKind::Interrupted {
+            return;
+        }
+    }
+}
+
+/// The child's handle already keeps its PID reserved until it is dropped.
+#[cfg(not(unix))]
+fn wait_exited(_child: &Child) {}
+
+/// Quotes `args` as one line for `--read-args-from-stdin`: the tool splits it on
+/// whitespace, honours double quotes and escapes the next character with a
+/// backslash.
+pub(crate) fn args_line(args: &[String]) -> Result<String, WkhtmlError> {
+    let mut quoted = Vec::with_capacity(args.len());
+    for arg in args {
+        if arg.contains(['\n', '\r']) {
+            return Err(WkhtmlError::invalid_option(
+                READ_ARGS_FROM_STDIN,
+                "arguments read from stdin can't contain line breaks",
+            ));
+        }
+        let mut escaped = String::with_capacity(arg.len() + 2);
+        escaped.push('"');
+        for c in arg.chars() {
+            if c == '"' || c == '\\' {
+                escaped.push('\\');
+            }
+            escaped.push(c);
+        }
+        escaped.push('"');
+        quoted.push(escaped);
+    }
+    let line = quoted.join(" ");
+    if line.len() > MAX_ARGS_LINE {
+        return Err(WkhtmlError::invalid_option(
+            READ_ARGS_FROM_STDIN,
+            format!(
+                "the arguments take {} bytes, the tool reads at most {}",
+                line.len(),
+                MAX_ARGS_LINE
+            ),
+        ));
+    }
+    Ok(line)
+}
+
+/// Prefixes a relative path starting with a dash with `./`, so the tool does not
+/// read it as an option.
+pub(crate) fn escape_path(path: &str) -> String {
+    if path.starts_with('-') {
+        format!("./{}", path)
+    } else {
+        path.to_string()
+    }
+}
+
+/// Replaces the arguments equal to one of `secrets`.
+pub(crate) fn redact(args: &[String], secrets: &[String]) -> Vec<String> {
+    args.iter()
+        .map(|arg| {
+            if !arg.is_empty() && secrets.contains(arg) {
+                REDACTED.to_string()
+            } else {
+                arg.clone()
+            }
+        })
+        .collect()
+}
+
+/// Kills a process and the processes it started.
+#[cfg(unix)]
+pub(crate) fn kill_tree(pid: u32) {
+    // The child leads its own process group, see `Core::command`
+    unsafe {
+        libc::kill(-(pid as i32), libc::SIGKILL);
+    }
+}
+
+#[cfg(not(unix))]
+pub(crate) fn kill_tree(pid: u32) {
+    let _ = Command::new("taskkill")
+        .args(["/F", "/T", "/PID", &pid.to_string()])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
 }
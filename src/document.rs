@@ -0,0 +1,81 @@
+use crate::app::WkhtmlInput;
+use crate::pdf_options::{PageOptions, TocOptions};
+use crate::renderer::{RenderObject, RenderRequest};
+
+#[derive(Debug, Clone)]
+pub enum PdfObject<'a> {
+    Cover(WkhtmlInput<'a>),
+    Toc(TocOptions),
+    Page(WkhtmlInput<'a>, Box<PageOptions>),
+}
+
+/// An ordered list of objects rendered into a single PDF by one wkhtmltopdf
+/// process, e.g. a cover, a table of contents and several chapters.
+#[derive(Debug, Clone, Default)]
+pub struct PdfDocument<'a> {
+    pub objects: Vec<PdfObject<'a>>,
+}
+
+impl<'a> PdfDocument<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cover(&mut self, input: WkhtmlInput<'a>) -> &mut Self {
+        self.objects.push(PdfObject::Cover(input));
+        self
+    }
+
+    pub fn toc(&mut self, options: TocOptions) -> &mut Self {
+        self.objects.push(PdfObject::Toc(options));
+        self
+    }
+
+    pub fn page(&mut self, input: WkhtmlInput<'a>) -> &mut Self {
+        self.page_with_options(input, PageOptions::default())
+    }
+
+    pub fn page_with_options(&mut self, input: WkhtmlInput<'a>, options: PageOptions) -> &mut Self {
+        self.objects.push(PdfObject::Page(input, Box::new(options)));
+        self
+    }
+
+    /// Turns the document into a request, `args` being the global options.
+    pub fn to_request(&self, args: Vec<String>) -> RenderRequest<'a> {
+        let objects = self
+            .objects
+            .iter()
+            .map(|object| match object {
+                PdfObject::Cover(input) => RenderObject {
+                    keyword: Some("cover"),
+                    input: Some(input.clone()),
+                    args: Vec::new(),
+                },
+                PdfObject::Toc(options) => RenderObject {
+                    keyword: Some("toc"),
+                    input: None,
+                    args: options.to_args(),
+                },
+                PdfObject::Page(input, options) => RenderObject {
+                    keyword: Some("page"),
+                    input: Some(input.clone()),
+                    args: options.to_args(),
+                },
+            })
+            .collect();
+        let secrets = self
+            .objects
+            .iter()
+            .filter_map(|object| match object {
+                PdfObject::Page(_, options) => Some(options.secrets()),
+                _ => None,
+            })
+            .flatten()
+            .collect();
+        RenderRequest {
+            args,
+            objects,
+            secrets,
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use crate::app::WkhtmlError;
+use crate::core::Core;
+use log::{debug, error, info};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, SystemTime};
+use uuid::Uuid;
+
+/// Limits applied to the work dir by `Core::collect_garbage`.
+///
+/// Only files named like the ones `Core::get_out_path` creates (`<uuid>-name`)
+/// are considered, anything else in the directory is left alone. Outputs left
+/// in place with `keep` are still named that way and are collected as well.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GcPolicy {
+    /// Removes files that were last modified longer ago than this.
+    pub max_age: Option<Duration>,
+    /// Removes the oldest files until the total size is below this many bytes.
+    /// Renders in progress count towards it and may lose their output, so
+    /// leave enough room for them.
+    pub max_size: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GcReport {
+    pub removed: usize,
+    pub freed: u64,
+    /// Total size of the files left in the work dir.
+    pub remaining: u64,
+}
+
+/// A background collection started by `Core::spawn_gc`, stopped when dropped.
+#[derive(Debug)]
+pub struct GcHandle {
+    stop: Option<mpsc::Sender<()>>,
+    handle: Option<thread::JoinHandle<()>>,
+}
+
+impl GcHandle {
+    /// Stops the collection and waits for a pass in progress to finish.
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        drop(self.stop.take());
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for GcHandle {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+struct Entry {
+    path: PathBuf,
+    size: u64,
+    modified: SystemTime,
+}
+
+impl Core {
+    /// Removes orphaned outputs from the work dir according to `policy`.
+    pub fn collect_garbage(&self, policy: &GcPolicy) -> Result<GcReport, WkhtmlError> {
+        collect(&self.work_dir, policy)
+    }
+
+    /// Runs `collect_garbage` every `interval` on a background thread.
+    pub fn spawn_gc(&self, policy: GcPolicy, interval: Duration) -> GcHandle {
+        let work_dir = self.work_dir.clone();
+        let (stop, stopped) = mpsc::channel::<()>();
+        let handle = thread::spawn(move || {
+            while let Err(mpsc::RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                match collect(&work_dir, &policy) {
+                    Ok(report) => debug!("Work dir collection: {:?}", report),
+                    Err(e) => error!("Work dir collection failed: {}", e),
+                }
+            }
+        });
+        GcHandle {
+            stop: Some(stop),
+            handle: Some(handle),
+        }
+    }
+}
+
+fn collect(work_dir: &Path, policy: &GcPolicy) -> Result<GcReport, WkhtmlError> {
+    let now = SystemTime::now();
+    let mut entries = Vec::new();
+    let dir = fs::read_dir(work_dir).map_err(|e| WkhtmlError::io("Failed to read work dir", e))?;
+    for entry in dir.flatten() {
+        if !is_output_name(&entry.file_name().to_string_lossy()) {
+            continue;
+        }
+        let Ok(meta) = entry.metadata() else {
+            continue;
+        };
+        if meta.is_file() {
+            entries.push(Entry {
+                path: entry.path(),
+                size: meta.len(),
+                modified: meta.modified().unwrap_or(now),
+            });
+        }
+    }
+    // Oldest first, they are the first to go when over the quota
+    entries.sort_by_key(|entry| entry.modified);
+
+    let mut report = GcReport {
+        remaining: entries.iter().map(|entry| entry.size).sum(),
+        ..Default::default()
+    };
+    for entry in entries {
+        let age = now.duration_since(entry.modified).unwrap_or_default();
+        let expired = policy.max_age.is_some_and(|max_age| age > max_age);
+        let over_quota = policy
+            .max_size
+            .is_some_and(|max_size| report.remaining > max_size);
+        if !expired && !over_quota {
+            continue;
+        }
+        // Another process may have removed it in the meantime
+        if fs::remove_file(&entry.path).is_ok() {
+            report.removed += 1;
+            report.freed += entry.size;
+        }
+        report.remaining -= entry.size;
+    }
+    if report.removed > 0 {
+        info!(
+            "Removed {} file(s), {} bytes, from {}",
+            report.removed,
+            report.freed,
+            work_dir.display()
+        );
+    }
+    Ok(report)
+}
+
+/// Matches the `<uuid>-name` files created by `Core::get_out_path`.
+fn is_output_name(name: &str) -> bool {
+    match (name.get(..36), name.get(36..37)) {
+        (Some(uuid), Some("-")) => Uuid::parse_str(uuid).is_ok(),
+        _ => false,
+    }
+}
@@ -1,24 +1,30 @@
 use crate::app::WkhtmlError;
 use crate::app::WkhtmlInput;
-use crate::core::Core;
+use crate::batch::{run_batch, BatchConfig, BatchItem, BatchReport};
+use crate::core::{Core, RawArg};
+use crate::options::check_values;
+use crate::img_options::ImgOptions;
+use crate::pool::RenderPool;
+use crate::progress::{Progress, ProgressHandler};
+use crate::output::RenderOutput;
+use crate::renderer::{RenderRequest, Renderer};
+use crate::sandbox::SandboxPolicy;
+use crate::url_policy::UrlPolicy;
 use std::collections::{HashMap, HashSet};
-use std::path::PathBuf;
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
 use std::env;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub enum ImgFormat {
+    #[default]
     Jpg,
     Png,
     Bmp,
     Svg,
 }
 
-impl Default for ImgFormat {
-    fn default() -> Self {
-        ImgFormat::Jpg
-    }
-}
-
 impl std::fmt::Display for ImgFormat {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
@@ -31,9 +37,9 @@ impl std::fmt::Display for ImgFormat {
 }
 
 #[derive(Debug, Clone)]
-pub struct ImgApp {
-    pub app: Core,
-    pub options: HashMap<String, String>,
+pub struct ImgApp<R = Core> {
+    pub app: R,
+    pub options: ImgOptions,
     pub format: ImgFormat,
 }
 
@@ -44,20 +50,70 @@ impl ImgApp {
 
         Ok(Self {
             app: Core::new(wkhtmltoimg_cmd)?,
-            options: HashMap::new(),
+            options: ImgOptions::default(),
             format: ImgFormat::default(),
         })
     }
 
-    pub fn set_format(&mut self, format: ImgFormat) -> Result<&mut Self, WkhtmlError> {
-        let fmt_str = format.to_string();
-        self.format = format;
-        self.set_arg("format", &fmt_str)?;
+    pub fn set_work_dir(&mut self, work_dir: &str) -> Result<&mut Self, WkhtmlError> {
+        self.app.set_work_dir(work_dir)?;
         Ok(self)
     }
 
-    pub fn set_work_dir(&mut self, work_dir: &str) -> Result<&mut Self, WkhtmlError> {
-        self.app.set_work_dir(work_dir)?;
+    pub fn set_timeout(&mut self, timeout: Duration) -> Result<&mut Self, WkhtmlError> {
+        self.app.set_timeout(Some(timeout));
+        Ok(self)
+    }
+
+    /// Shares the slots of `pool` with the other apps using it, see `RenderPool`.
+    pub fn set_pool(&mut self, pool: &RenderPool) -> Result<&mut Self, WkhtmlError> {
+        self.app.set_pool(Some(pool.clone()));
+        Ok(self)
+    }
+
+    /// Renders untrusted content within `sandbox`, see `SandboxPolicy`.
+    pub fn set_sandbox(&mut self, sandbox: SandboxPolicy) -> Result<&mut Self, WkhtmlError> {
+        self.app.set_sandbox(Some(sandbox));
+        Ok(self)
+    }
+
+    /// Refuses URL inputs that `url_policy` does not allow, see `UrlPolicy`.
+    pub fn set_url_policy(&mut self, url_policy: UrlPolicy) -> Result<&mut Self, WkhtmlError> {
+        self.app.set_url_policy(Some(url_policy));
+        Ok(self)
+    }
+
+    /// Calls `callback` with the phase and percentage of every render as the
+    /// tool reports them.
+    pub fn on_progress(
+        &mut self,
+        callback: impl Fn(&Progress) + Send + Sync + 'static,
+    ) -> Result<&mut Self, WkhtmlError> {
+        self.app.set_progress(Some(ProgressHandler::new(callback)));
+        Ok(self)
+    }
+
+    /// Returns a copy of this app whose renders are killed after `timeout`,
+    /// for a single call: `app.with_timeout(timeout).run(input, name)`.
+    pub fn with_timeout(&self, timeout: Duration) -> Self {
+        let mut app = self.clone();
+        app.app.set_timeout(Some(timeout));
+        app
+    }
+}
+
+impl<R: Renderer> ImgApp<R> {
+    /// Builds an app on top of another backend, such as a `MockRenderer`.
+    pub fn with_renderer(renderer: R) -> Self {
+        Self {
+            app: renderer,
+            options: ImgOptions::default(),
+            format: ImgFormat::default(),
+        }
+    }
+
+    pub fn set_format(&mut self, format: ImgFormat) -> Result<&mut Self, WkhtmlError> {
+        self.format = format;
         Ok(self)
     }
 
@@ -69,18 +125,109 @@ impl ImgApp {
     }
 
     pub fn set_arg(&mut self, key: &str, arg: &str) -> Result<&mut Self, WkhtmlError> {
-        if Self::validate_option(key) {
-            self.options.insert(key.into(), arg.into());
-            Ok(self)
-        } else {
-            Err(WkhtmlError::ServiceErr(format!("Invalid option: {}", key)))
+        Self::check_option(key, &[arg])?;
+        self.options.extra.retain(|option| option.key != key);
+        self.options.extra.push(RawArg::new(key, &[arg]));
+        Ok(self)
+    }
+
+    pub fn add_arg(&mut self, key: &str, values: &[&str]) -> Result<&mut Self, WkhtmlError> {
+        Self::check_option(key, values)?;
+        self.options.extra.push(RawArg::new(key, values));
+        Ok(self)
+    }
+
+    /// Like `add_arg`, for values redacted from logs and `RenderOutput::command_line`.
+    pub fn add_secret_arg(&mut self, key: &str, values: &[&str]) -> Result<&mut Self, WkhtmlError> {
+        Self::check_option(key, values)?;
+        self.options.extra.push(RawArg::secret(key, values));
+        Ok(self)
+    }
+
+    pub fn set_options(&mut self, options: ImgOptions) -> Result<&mut Self, WkhtmlError> {
+        Self::check_options(&options)?;
+        options.validate(&self.format)?;
+        self.options = options;
+        Ok(self)
+    }
+
+    fn check_options(options: &ImgOptions) -> Result<(), WkhtmlError> {
+        for option in &options.extra {
+            let values: Vec<&str> = option.values.iter().map(String::as_str).collect();
+            Self::check_option(&option.key, &values)?;
         }
+        Ok(())
     }
 
-    pub fn run(&self, input: WkhtmlInput, name: &str) -> Result<PathBuf, WkhtmlError> {
+    fn check_option(key: &str, values: &[&str]) -> Result<(), WkhtmlError> {
+        if !Self::validate_option(key) {
+            return Err(WkhtmlError::invalid_option(key, "unknown option"));
+        }
+        if key == "format" {
+            // The format is always emitted from `self.format`, a raw one would conflict
+            return Err(WkhtmlError::invalid_option(key, "use set_format instead"));
+        }
+        check_values(key, values)
+    }
+
+    pub fn run(&self, input: WkhtmlInput, name: &str) -> Result<RenderOutput, WkhtmlError> {
         let name = format!("{}.{}", name, self.format);
-        let args = Core::build_args(&self.options);
-        self.app.run(input, &name, args)
+        let request = RenderRequest::new(self.options.to_args(&self.format)?, input)
+            .with_secrets(self.options.secrets());
+        self.app.render_to_file(request, &name)
+    }
+
+    /// Renders straight to `path`, replacing it atomically once the render succeeded.
+    pub fn run_to_path(
+        &self,
+        input: WkhtmlInput,
+        path: impl AsRef<Path>,
+    ) -> Result<RenderOutput, WkhtmlError> {
+        let request = RenderRequest::new(self.options.to_args(&self.format)?, input)
+            .with_secrets(self.options.secrets());
+        self.app.render_to_path(request, path.as_ref())
+    }
+
+    /// Renders every item, `config.parallelism` at a time, returning one result
+    /// per item in input order.
+    pub fn render_batch<'a>(
+        &self,
+        items: impl IntoIterator<Item = BatchItem<'a, ImgOptions>>,
+        config: &BatchConfig,
+    ) -> BatchReport
+    where
+        R: Sync,
+    {
+        run_batch(items, config, |item| {
+            let options = match &item.options {
+                Some(options) => {
+                    Self::check_options(options)?;
+                    options
+                }
+                None => &self.options,
+            };
+            let name = format!("{}.{}", item.name, self.format);
+            let request = RenderRequest::new(options.to_args(&self.format)?, item.input.clone())
+                .with_secrets(options.secrets());
+            self.app.render_to_file(request, &name)
+        })
+    }
+
+    pub fn render_to_vec(&self, input: WkhtmlInput) -> Result<Vec<u8>, WkhtmlError> {
+        let request = RenderRequest::new(self.options.to_args(&self.format)?, input)
+            .with_secrets(self.options.secrets());
+        let output = self.app.render_to_bytes(request)?;
+        Ok(output.into_bytes().unwrap_or_default())
+    }
+
+    pub fn run_to_writer<W: Write>(
+        &self,
+        input: WkhtmlInput,
+        writer: &mut W,
+    ) -> Result<u64, WkhtmlError> {
+        let request = RenderRequest::new(self.options.to_args(&self.format)?, input)
+            .with_secrets(self.options.secrets());
+        Ok(self.app.render_to_writer(request, writer)?.size)
     }
 
     fn validate_option(key: &str) -> bool {
@@ -0,0 +1,200 @@
+use crate::app::WkhtmlError;
+use crate::core::{Core, RawArg};
+use crate::img::ImgFormat;
+use crate::options::{
+    push_flag, push_pairs, push_repeated, push_toggle, push_value, LoadErrorHandling,
+};
+
+/// Area of the rendered page to keep, in pixels.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CropRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Typed wkhtmltoimage options.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ImgOptions {
+    // Image options
+    pub crop: Option<CropRect>,
+    /// Output quality between 0 and 100, only used for JPG and PNG output.
+    pub quality: Option<u8>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// Transparent background, only supported for PNG output.
+    pub transparent: bool,
+    pub smart_width: Option<bool>,
+    pub zoom: Option<f32>,
+    // Loading options
+    pub allow: Vec<String>,
+    pub bypass_proxy_for: Vec<String>,
+    pub cache_dir: Option<String>,
+    pub checkbox_checked_svg: Option<String>,
+    pub checkbox_svg: Option<String>,
+    pub cookies: Vec<(String, String)>,
+    pub cookie_jar: Option<String>,
+    pub custom_headers: Vec<(String, String)>,
+    pub custom_header_propagation: Option<bool>,
+    pub debug_javascript: Option<bool>,
+    pub encoding: Option<String>,
+    pub images: Option<bool>,
+    pub javascript: Option<bool>,
+    pub javascript_delay: Option<u32>,
+    pub load_error_handling: Option<LoadErrorHandling>,
+    pub load_media_error_handling: Option<LoadErrorHandling>,
+    pub local_file_access: Option<bool>,
+    pub minimum_font_size: Option<u32>,
+    pub password: Option<String>,
+    pub plugins: Option<bool>,
+    pub post: Vec<(String, String)>,
+    pub post_files: Vec<(String, String)>,
+    pub proxy: Option<String>,
+    pub quiet: bool,
+    pub radiobutton_checked_svg: Option<String>,
+    pub radiobutton_svg: Option<String>,
+    pub run_script: Vec<String>,
+    pub stop_slow_scripts: Option<bool>,
+    pub use_xserver: bool,
+    pub user_style_sheet: Option<String>,
+    pub username: Option<String>,
+    pub window_status: Option<String>,
+    /// Raw options set through `ImgApp::set_arg` and `ImgApp::add_arg`.
+    pub extra: Vec<RawArg>,
+}
+
+impl ImgOptions {
+    /// Values of the options that must stay out of logs: the password, cookie
+    /// and header values, and raw options marked secret.
+    pub fn secrets(&self) -> Vec<String> {
+        let pairs = self.cookies.iter().chain(&self.custom_headers);
+        let mut secrets: Vec<String> = pairs.map(|(_, value)| value.clone()).collect();
+        secrets.extend(self.password.iter().cloned());
+        secrets.extend(self.extra.iter().flat_map(|option| option.secret_values()).cloned());
+        secrets
+    }
+
+    pub fn validate(&self, format: &ImgFormat) -> Result<(), WkhtmlError> {
+        if let Some(quality) = self.quality {
+            if quality > 100 {
+                return Err(WkhtmlError::invalid_option(
+                    "quality",
+                    format!("must be between 0 and 100, got {}", quality),
+                ));
+            }
+        }
+        // Raw options set with `set_arg` are held to the same rules as the typed ones
+        let raw = |key: &str| self.extra.iter().any(|option| option.key == key);
+        if (self.quality.is_some() || raw("quality"))
+            && !matches!(format, ImgFormat::Jpg | ImgFormat::Png)
+        {
+            return Err(WkhtmlError::invalid_option(
+                "quality",
+                format!("not supported for {} output", format),
+            ));
+        }
+        if (self.transparent || raw("transparent")) && !matches!(format, ImgFormat::Png) {
+            return Err(WkhtmlError::invalid_option(
+                "transparent",
+                format!("only supported for png output, not {}", format),
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn to_args(&self, format: &ImgFormat) -> Result<Vec<String>, WkhtmlError> {
+        self.validate(format)?;
+        let mut args = vec!["--format".to_string(), format.to_string()];
+        // Image options
+        if let Some(crop) = &self.crop {
+            push_value(&mut args, "crop-x", &Some(crop.x));
+            push_value(&mut args, "crop-y", &Some(crop.y));
+            push_value(&mut args, "crop-w", &Some(crop.width));
+            push_value(&mut args, "crop-h", &Some(crop.height));
+        }
+        push_value(&mut args, "quality", &self.quality);
+        push_value(&mut args, "width", &self.width);
+        push_value(&mut args, "height", &self.height);
+        push_flag(&mut args, "transparent", self.transparent);
+        push_toggle(
+            &mut args,
+            "enable-smart-width",
+            "disable-smart-width",
+            self.smart_width,
+        );
+        push_value(&mut args, "zoom", &self.zoom);
+        // Loading options
+        push_repeated(&mut args, "allow", &self.allow);
+        push_repeated(&mut args, "bypass-proxy-for", &self.bypass_proxy_for);
+        push_value(&mut args, "cache-dir", &self.cache_dir);
+        push_value(
+            &mut args,
+            "checkbox-checked-svg",
+            &self.checkbox_checked_svg,
+        );
+        push_value(&mut args, "checkbox-svg", &self.checkbox_svg);
+        push_pairs(&mut args, "cookie", &self.cookies);
+        push_value(&mut args, "cookie-jar", &self.cookie_jar);
+        push_pairs(&mut args, "custom-header", &self.custom_headers);
+        push_toggle(
+            &mut args,
+            "custom-header-propagation",
+            "no-custom-header-propagation",
+            self.custom_header_propagation,
+        );
+        push_toggle(
+            &mut args,
+            "debug-javascript",
+            "no-debug-javascript",
+            self.debug_javascript,
+        );
+        push_value(&mut args, "encoding", &self.encoding);
+        push_toggle(&mut args, "images", "no-images", self.images);
+        push_toggle(
+            &mut args,
+            "enable-javascript",
+            "disable-javascript",
+            self.javascript,
+        );
+        push_value(&mut args, "javascript-delay", &self.javascript_delay);
+        push_value(&mut args, "load-error-handling", &self.load_error_handling);
+        push_value(
+            &mut args,
+            "load-media-error-handling",
+            &self.load_media_error_handling,
+        );
+        push_toggle(
+            &mut args,
+            "enable-local-file-access",
+            "disable-local-file-access",
+            self.local_file_access,
+        );
+        push_value(&mut args, "minimum-font-size", &self.minimum_font_size);
+        push_value(&mut args, "password", &self.password);
+        push_toggle(&mut args, "enable-plugins", "disable-plugins", self.plugins);
+        push_pairs(&mut args, "post", &self.post);
+        push_pairs(&mut args, "post-file", &self.post_files);
+        push_value(&mut args, "proxy", &self.proxy);
+        push_flag(&mut args, "quiet", self.quiet);
+        push_value(
+            &mut args,
+            "radiobutton-checked-svg",
+            &self.radiobutton_checked_svg,
+        );
+        push_value(&mut args, "radiobutton-svg", &self.radiobutton_svg);
+        push_repeated(&mut args, "run-script", &self.run_script);
+        push_toggle(
+            &mut args,
+            "stop-slow-scripts",
+            "no-stop-slow-scripts",
+            self.stop_slow_scripts,
+        );
+        push_flag(&mut args, "use-xserver", self.use_xserver);
+        push_value(&mut args, "user-style-sheet", &self.user_style_sheet);
+        push_value(&mut args, "username", &self.username);
+        push_value(&mut args, "window-status", &self.window_status);
+        args.extend(Core::build_args(&self.extra));
+        Ok(args)
+    }
+}
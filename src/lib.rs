@@ -1,15 +1,72 @@
 mod core;
 mod app;
+#[cfg(feature = "tokio")]
+mod asynchronous;
+mod document;
+mod options;
 mod pdf;
+mod pdf_options;
 mod img;
+mod img_options;
+mod renderer;
+mod mock;
+mod warnings;
+mod progress;
+mod output;
+mod gc;
+mod pool;
+mod batch;
+mod worker;
+mod sandbox;
+mod url_policy;
+pub use crate::core::{Core, RawArg};
 pub use app::*;
+pub use batch::*;
+pub use document::*;
+pub use gc::*;
 pub use img::*;
+pub use img_options::*;
+pub use mock::*;
+pub use options::*;
+pub use output::*;
 pub use pdf::*;
+pub use pdf_options::*;
+pub use pool::{PoolConfig, PoolStats, RenderPool};
+pub use progress::{Progress, ProgressHandler};
+pub use renderer::*;
+pub use sandbox::*;
+pub use url_policy::*;
+pub use warnings::*;
+pub use worker::PersistentWorker;
 #[cfg(test)]
 mod tests {
     use std::collections::HashMap;
+    use std::fs;
+    use std::time::Duration;
 
-    use crate::{ImgApp, ImgFormat, PdfApp, WkhtmlInput};
+    use crate::core::Core;
+    use crate::{
+        parse_warnings, BatchConfig, BatchItem, BatchMode, CropRect, GcPolicy, GcReport, ImgApp,
+        ImgFormat, ImgOptions, IpRange, Length, MockRenderer, Orientation, PageOptions, PageSize,
+        PdfApp, PdfDocument, PdfOptions, PersistentWorker, PoolConfig, PoolStats, RecordedInput,
+        RecordedRender, RenderPool, RenderRequest, Renderer, SandboxPolicy, TocOptions, UrlPolicy,
+        WarningKind, WkhtmlError, WkhtmlInput,
+    };
+
+    /// Writes a shell script standing in for wkhtmltopdf, answering the `-V`
+    /// bootstrap check and running `script` for renders.
+    #[cfg(unix)]
+    fn stand_in(name: &str, script: &str) -> String {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join("wkhtmlapp-tests");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        let script = format!("#!/bin/sh\n[ \"$1\" = \"-V\" ] && exit 0\n{}\n", script);
+        fs::write(&path, script).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        path.to_str().unwrap().to_string()
+    }
 
     #[test]
     fn test_pdf() {
@@ -22,7 +79,7 @@ mod tests {
         let html_code = r#"<html><body><div>DEMO</div></body></html>"#;
         let res = pdf_app.run(WkhtmlInput::Html(html_code), "demo");
         assert!(res.is_ok(), "{}", res.unwrap_err());
-        assert!(res.unwrap().extension().unwrap() == "pdf");
+        assert!(res.unwrap().path().unwrap().extension().unwrap() == "pdf");
 
         // Test building PDF from file
         let res = pdf_app.run(WkhtmlInput::File("examples/index.html"), "demo");
@@ -53,4 +110,1470 @@ mod tests {
         let res = image_app.run(WkhtmlInput::Url("https://wkhtmltopdf.org/"), "demo");
         assert!(res.is_ok(), "{}", res.unwrap_err());
     }
+
+    #[test]
+    fn test_pdf_options_args() {
+        let options = PdfOptions {
+            margin_top: Some(Length::mm(18.0)),
+            orientation: Some(Orientation::Landscape),
+            page_size: Some(PageSize::A4),
+            page: PageOptions {
+                cookies: vec![("session".into(), "abc".into())],
+                smart_shrinking: Some(true),
+                ..Default::default()
+            },
+            toc: Some(TocOptions {
+                header_text: Some("Contents".into()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let mut pdf_app = PdfApp::with_renderer(MockRenderer::new());
+        pdf_app.set_options(options).unwrap();
+        let input = WkhtmlInput::Url("https://example.com");
+        pdf_app.run(input, "options").unwrap();
+        assert_eq!(
+            pdf_app.app.last_render().unwrap().args,
+            vec![
+                "--margin-top",
+                "18mm",
+                "--orientation",
+                "Landscape",
+                "--page-size",
+                "A4",
+                "--cookie",
+                "session",
+                "abc",
+                "--enable-smart-shrinking",
+                "toc",
+                "--toc-header-text",
+                "Contents",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_img_options_args() {
+        let options = ImgOptions {
+            crop: Some(CropRect {
+                x: 0,
+                y: 10,
+                width: 200,
+                height: 100,
+            }),
+            quality: Some(80),
+            ..Default::default()
+        };
+        assert_eq!(
+            options.to_args(&ImgFormat::Jpg).unwrap(),
+            vec![
+                "--format",
+                "jpg",
+                "--crop-x",
+                "0",
+                "--crop-y",
+                "10",
+                "--crop-w",
+                "200",
+                "--crop-h",
+                "100",
+                "--quality",
+                "80",
+            ]
+        );
+        assert!(options.to_args(&ImgFormat::Svg).is_err());
+
+        let options = ImgOptions {
+            quality: Some(101),
+            ..Default::default()
+        };
+        assert!(options.to_args(&ImgFormat::Png).is_err());
+
+        let options = ImgOptions {
+            transparent: true,
+            ..Default::default()
+        };
+        assert!(options.to_args(&ImgFormat::Png).is_ok());
+        assert!(options.to_args(&ImgFormat::Jpg).is_err());
+
+        let mut img_app = ImgApp::with_renderer(MockRenderer::new());
+        assert!(img_app.set_arg("format", "png").is_err());
+        img_app
+            .set_format(ImgFormat::Svg)
+            .unwrap()
+            .set_arg("quality", "50")
+            .unwrap();
+        assert!(img_app.options.to_args(&ImgFormat::Svg).is_err());
+        assert!(img_app.options.to_args(&ImgFormat::Jpg).is_ok());
+    }
+
+    #[test]
+    fn test_repeated_args() {
+        let mut pdf_app = PdfApp::with_renderer(MockRenderer::new());
+        pdf_app
+            .add_arg("cookie", &["session", "abc"])
+            .unwrap()
+            .add_arg("cookie", &["theme", "dark"])
+            .unwrap()
+            .add_arg("allow", &["/srv/assets"])
+            .unwrap()
+            .set_arg("grayscale", "true")
+            .unwrap();
+        assert!(pdf_app.set_arg("cookie", "session=abc").is_err());
+        let input = WkhtmlInput::Url("https://example.com");
+        pdf_app.run(input, "repeated").unwrap();
+        assert_eq!(
+            pdf_app.app.last_render().unwrap().args,
+            vec![
+                "--grayscale",
+                "--allow",
+                "/srv/assets",
+                "--cookie",
+                "session",
+                "abc",
+                "--cookie",
+                "theme",
+                "dark",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_args_order() {
+        let mut first = PdfApp::with_renderer(MockRenderer::new());
+        first
+            .set_arg("header-right", "[page]")
+            .unwrap()
+            .set_arg("toc", "true")
+            .unwrap()
+            .set_arg("zoom", "1.2")
+            .unwrap()
+            .set_arg("title", "Report")
+            .unwrap()
+            .set_arg("disable-dotted-lines", "true")
+            .unwrap()
+            .set_arg("dpi", "300")
+            .unwrap();
+        let mut second = PdfApp::with_renderer(MockRenderer::new());
+        second
+            .set_arg("dpi", "300")
+            .unwrap()
+            .set_arg("disable-dotted-lines", "true")
+            .unwrap()
+            .set_arg("title", "Report")
+            .unwrap()
+            .set_arg("zoom", "1.2")
+            .unwrap()
+            .set_arg("toc", "true")
+            .unwrap()
+            .set_arg("header-right", "[page]")
+            .unwrap();
+        let expected = vec![
+            "--dpi",
+            "300",
+            "--title",
+            "Report",
+            "--zoom",
+            "1.2",
+            "--header-right",
+            "[page]",
+            "toc",
+            "--disable-dotted-lines",
+        ];
+        let input = WkhtmlInput::Url("https://example.com");
+        for app in [&first, &second] {
+            app.run(input.clone(), "ordered").unwrap();
+            assert_eq!(app.app.last_render().unwrap().args, expected);
+        }
+        assert_eq!(first.options.to_args(), expected);
+
+        assert!(first.set_arg("cover", "true").is_err());
+        assert!(first.add_arg("cover", &[]).is_err());
+        first.options.toc = Some(TocOptions::default());
+        first.run(input, "toc").unwrap();
+        let args = first.app.last_render().unwrap().args;
+        assert_eq!(args.iter().filter(|arg| *arg == "toc").count(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_pdf_document() {
+        let cmd = stand_in(
+            "document",
+            r#"printf '%s\n' "$@" > "$0.args"
+for a in "$@"; do out="$a"; done
+cat > /dev/null
+echo pdf > "$out""#,
+        );
+        let pdf_app = PdfApp {
+            app: Core::new(cmd.clone()).unwrap(),
+            options: PdfOptions {
+                grayscale: true,
+                ..Default::default()
+            },
+        };
+        let mut document = PdfDocument::new();
+        document
+            .cover(WkhtmlInput::File("examples/index.html"))
+            .toc(TocOptions {
+                header_text: Some("Contents".into()),
+                ..Default::default()
+            })
+            .page(WkhtmlInput::Html("<p>Chapter 1</p>"))
+            .page_with_options(
+                WkhtmlInput::Html("<p>Chapter 2</p>"),
+                PageOptions {
+                    zoom: Some(1.5),
+                    ..Default::default()
+                },
+            );
+        let res = pdf_app.run_document(&document, "report");
+        assert!(res.is_ok(), "{}", res.unwrap_err());
+
+        let args = fs::read_to_string(format!("{}.args", cmd)).unwrap();
+        let args: Vec<&str> = args.lines().collect();
+        assert_eq!(
+            args[..9],
+            [
+                "--grayscale",
+                "cover",
+                "examples/index.html",
+                "toc",
+                "--toc-header-text",
+                "Contents",
+                "page",
+                "-",
+                "page",
+            ]
+        );
+        // The second HTML page goes through a temporary file, removed after the render
+        assert!(args[9].ends_with("input.html"));
+        assert!(!std::path::Path::new(args[9]).exists());
+        assert_eq!(args[10..12], ["--zoom", "1.5"]);
+
+        assert!(pdf_app.run_document(&PdfDocument::new(), "report").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_render_to_vec() {
+        let cmd = stand_in(
+            "stdout",
+            r#"for a in "$@"; do out="$a"; done
+cat > /dev/null
+[ "$out" = "-" ] && printf '%%PDF-1.4 stand-in'"#,
+        );
+        let work_dir = std::env::temp_dir().join("wkhtmlapp-tests/render-to-vec");
+        let mut pdf_app = PdfApp {
+            app: Core::new(cmd).unwrap(),
+            options: PdfOptions::default(),
+        };
+        pdf_app.set_work_dir(work_dir.to_str().unwrap()).unwrap();
+
+        let bytes = pdf_app
+            .render_to_vec(WkhtmlInput::Html("<p>DEMO</p>"))
+            .unwrap();
+        assert_eq!(bytes, b"%PDF-1.4 stand-in");
+        assert_eq!(fs::read_dir(&work_dir).unwrap().count(), 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_to_writer() {
+        struct FailingWriter;
+
+        impl std::io::Write for FailingWriter {
+            fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+                Err(std::io::Error::other("socket closed"))
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let cmd = stand_in(
+            "writer",
+            r#"for a in "$@"; do out="$a"; done
+[ "$out" = "-" ] && head -c 300000 /dev/zero"#,
+        );
+        let pdf_app = PdfApp {
+            app: Core::new(cmd).unwrap(),
+            options: PdfOptions::default(),
+        };
+
+        let mut sink = Vec::new();
+        let written = pdf_app
+            .run_to_writer(WkhtmlInput::File("examples/index.html"), &mut sink)
+            .unwrap();
+        assert_eq!(written, 300000);
+        assert_eq!(sink.len(), 300000);
+
+        let res =
+            pdf_app.run_to_writer(WkhtmlInput::File("examples/index.html"), &mut FailingWriter);
+        assert!(matches!(res, Err(WkhtmlError::Writer(_))));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_timeout() {
+        let cmd = stand_in(
+            "timeout",
+            r#"echo "Loading pages (1/6)" >&2
+sleep 30
+sleep 30"#,
+        );
+        let mut pdf_app = PdfApp {
+            app: Core::new(cmd).unwrap(),
+            options: PdfOptions::default(),
+        };
+
+        let started = std::time::Instant::now();
+        let res = pdf_app
+            .with_timeout(Duration::from_millis(300))
+            .run(WkhtmlInput::File("examples/index.html"), "demo");
+        // The sleeping grandchild holds stderr open, so this only returns early if it was killed too
+        assert!(started.elapsed() < Duration::from_secs(10));
+        match res {
+            Err(WkhtmlError::Timeout { timeout, stderr }) => {
+                assert_eq!(timeout, Duration::from_millis(300));
+                assert!(stderr.contains("Loading pages"), "{}", stderr);
+            }
+            other => panic!("expected a timeout, got {:?}", other),
+        }
+        assert_eq!(pdf_app.app.timeout, None);
+
+        pdf_app.set_timeout(Duration::from_millis(300)).unwrap();
+        let res = pdf_app.run_to_writer(WkhtmlInput::Html("<p>DEMO</p>"), &mut Vec::new());
+        assert!(matches!(res, Err(WkhtmlError::Timeout { .. })));
+    }
+
+    #[cfg(all(unix, feature = "tokio"))]
+    #[tokio::test]
+    async fn test_run_async() {
+        let cmd = stand_in(
+            "async",
+            r#"for a in "$@"; do out="$a"; done
+cat > /dev/null
+echo pdf > "$out""#,
+        );
+        let mut pdf_app = PdfApp {
+            app: Core::new(cmd).unwrap(),
+            options: PdfOptions::default(),
+        };
+        let res = pdf_app
+            .run_async(WkhtmlInput::Html("<p>DEMO</p>"), "demo")
+            .await;
+        assert!(res.is_ok(), "{}", res.unwrap_err());
+        assert_eq!(
+            fs::read_to_string(res.unwrap().path().unwrap()).unwrap(),
+            "pdf\n"
+        );
+
+        pdf_app.app.wkhtmltox_cmd = stand_in("async-slow", "sleep 30");
+        pdf_app.set_timeout(Duration::from_millis(200)).unwrap();
+        let res = pdf_app
+            .run_async(WkhtmlInput::File("examples/index.html"), "demo")
+            .await;
+        assert!(matches!(res, Err(WkhtmlError::Timeout { .. })));
+    }
+
+    #[cfg(all(unix, feature = "tokio"))]
+    #[tokio::test]
+    async fn test_run_async_cancel() {
+        let cmd = stand_in(
+            "async-cancel",
+            r#"sleep 1
+echo finished > "$0.done""#,
+        );
+        let done = format!("{}.done", cmd);
+        let _ = fs::remove_file(&done);
+        let mut pdf_app = PdfApp {
+            app: Core::new(cmd).unwrap(),
+            options: PdfOptions::default(),
+        };
+
+        // Dropping the future when the outer timeout expires kills the child
+        let render = pdf_app.run_async(WkhtmlInput::File("examples/index.html"), "demo");
+        assert!(tokio::time::timeout(Duration::from_millis(200), render)
+            .await
+            .is_err());
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        assert!(!std::path::Path::new(&done).exists());
+
+        // and removes the temporary HTML inputs
+        let work_dir = std::env::temp_dir().join("wkhtmlapp-tests/async-cancel-work");
+        let _ = fs::remove_dir_all(&work_dir);
+        pdf_app
+            .app
+            .set_work_dir(work_dir.to_str().unwrap())
+            .unwrap()
+            .set_secure_args(true);
+        let render = pdf_app.run_async(WkhtmlInput::Html("<p>hi</p>"), "demo");
+        let render = tokio::time::timeout(Duration::from_millis(200), render);
+        assert!(render.await.is_err());
+        assert_eq!(fs::read_dir(&work_dir).unwrap().count(), 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_large_html_with_noisy_stderr() {
+        // Writes 1 MB of warnings before reading its input, which fills the stderr
+        // pipe while the HTML is still being written to stdin
+        let cmd = stand_in(
+            "noisy",
+            r#"for a in "$@"; do out="$a"; done
+head -c 1048576 /dev/zero | tr '\000' 'w' >&2
+wc -c > "$out""#,
+        );
+        let mut pdf_app = PdfApp {
+            app: Core::new(cmd).unwrap(),
+            options: PdfOptions::default(),
+        };
+        // Fails instead of hanging forever if the deadlock comes back
+        pdf_app.set_timeout(Duration::from_secs(20)).unwrap();
+
+        let html = format!("<html><body>{}</body></html>", "x".repeat(20 * 1024 * 1024));
+        let res = pdf_app.run(WkhtmlInput::Html(&html), "noisy");
+        assert!(res.is_ok(), "{}", res.unwrap_err());
+        let size = fs::read_to_string(res.unwrap().path().unwrap()).unwrap();
+        assert_eq!(size.trim().parse::<usize>().unwrap(), html.len());
+    }
+
+    #[test]
+    fn test_mock_renderer() {
+        let mock = MockRenderer::returning("%PDF-1.4 mock");
+        let mut pdf_app = PdfApp::with_renderer(mock.clone());
+        pdf_app.set_arg("grayscale", "true").unwrap();
+
+        let output = pdf_app
+            .run(WkhtmlInput::Url("https://example.com"), "demo")
+            .unwrap();
+        assert_eq!(fs::read(output.path().unwrap()).unwrap(), b"%PDF-1.4 mock");
+        assert_eq!(
+            pdf_app
+                .render_to_vec(WkhtmlInput::Html("<p>DEMO</p>"))
+                .unwrap(),
+            b"%PDF-1.4 mock"
+        );
+        let mut document = PdfDocument::new();
+        document
+            .toc(TocOptions::default())
+            .page(WkhtmlInput::File("examples/index.html"));
+        pdf_app.run_document(&document, "report").unwrap();
+
+        let renders = mock.renders();
+        assert_eq!(renders.len(), 3);
+        assert_eq!(
+            renders[0],
+            RecordedRender {
+                args: vec!["--grayscale".to_string()],
+                inputs: vec![RecordedInput::Url("https://example.com".to_string())],
+                name: Some("demo.pdf".to_string()),
+            }
+        );
+        assert_eq!(
+            renders[1].inputs,
+            [RecordedInput::Html("<p>DEMO</p>".to_string())]
+        );
+        assert_eq!(renders[1].name, None);
+        assert_eq!(renders[2].args, ["--grayscale", "toc", "page"]);
+
+        let mock = MockRenderer::failing(WkhtmlError::NonZeroExit {
+            code: Some(1),
+            stderr: "boom".to_string(),
+            warnings: Vec::new(),
+        });
+        let mut img_app = ImgApp::with_renderer(mock.clone());
+        img_app.set_format(ImgFormat::Png).unwrap();
+        let res = img_app.run(WkhtmlInput::Html("<p>DEMO</p>"), "demo");
+        assert!(matches!(res, Err(WkhtmlError::NonZeroExit { .. })));
+        let render = mock.last_render().unwrap();
+        assert_eq!(render.args, ["--format", "png"]);
+        assert_eq!(render.name.as_deref(), Some("demo.png"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_structured_errors() {
+        use std::error::Error;
+
+        let res = Core::new("wkhtmlapp-missing-binary".to_string());
+        match res {
+            Err(err @ WkhtmlError::BinaryNotFound { .. }) => assert!(err.source().is_some()),
+            other => panic!("expected BinaryNotFound, got {:?}", other),
+        }
+
+        let cmd = stand_in("failing", "echo 'Error: Failed loading page' >&2\nexit 2");
+        let mut pdf_app = PdfApp {
+            app: Core::new(cmd).unwrap(),
+            options: PdfOptions::default(),
+        };
+        match pdf_app.run(WkhtmlInput::File("examples/index.html"), "demo") {
+            Err(WkhtmlError::NonZeroExit { code, stderr, .. }) => {
+                assert_eq!(code, Some(2));
+                assert!(stderr.contains("Failed loading page"));
+            }
+            other => panic!("expected NonZeroExit, got {:?}", other),
+        }
+
+        match pdf_app.set_arg("no-such-option", "true") {
+            Err(WkhtmlError::InvalidOption { key, .. }) => assert_eq!(key, "no-such-option"),
+            other => panic!("expected InvalidOption, got {:?}", other.map(|_| ())),
+        }
+        assert!(matches!(
+            pdf_app.set_work_dir("Cargo.toml"),
+            Err(WkhtmlError::InvalidWorkDir { .. })
+        ));
+    }
+
+    #[test]
+    fn test_parse_warnings() {
+        let stderr = "Loading pages (1/6)\n\
+[==========>     ] 60%\rWarning: Failed to load file:///x.png (ignore)\n\
+Warning: Received createRequest signal on a disposed ResourceObject's NetworkAccessManager.\n\
+Error: Failed to load about:blank, with network status code 301 and http status code 0\n\
+Error: Failed loading page http://localhost:1/ (sometimes it will work just to ignore this error with --load-error-handling ignore)\n\
+Exit with code 1 due to network error: ConnectionRefusedError\n";
+        let warnings = parse_warnings(stderr);
+        let kinds: Vec<WarningKind> = warnings.iter().map(|warning| warning.kind).collect();
+        assert_eq!(
+            kinds,
+            [
+                WarningKind::ResourceLoadFailed,
+                WarningKind::Warning,
+                WarningKind::ResourceLoadFailed,
+                WarningKind::PageLoadFailed,
+            ]
+        );
+        assert_eq!(warnings[0].url.as_deref(), Some("file:///x.png"));
+        assert_eq!(warnings[0].message, "Failed to load file:///x.png (ignore)");
+        assert_eq!(warnings[1].url, None);
+        assert_eq!(warnings[2].url.as_deref(), Some("about:blank"));
+        assert_eq!(warnings[3].url.as_deref(), Some("http://localhost:1/"));
+        assert!(warnings[3].is_error());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_render_warnings() {
+        let cmd = stand_in(
+            "warnings",
+            r#"for a in "$@"; do out="$a"; done
+echo 'Loading pages (1/6)' >&2
+echo 'Warning: Failed to load http://localhost:1/logo.png (ignore)' >&2
+echo pdf > "$out""#,
+        );
+        let pdf_app = PdfApp {
+            app: Core::new(cmd).unwrap(),
+            options: PdfOptions::default(),
+        };
+        let rendered = pdf_app
+            .run(WkhtmlInput::File("examples/index.html"), "demo")
+            .unwrap();
+        assert!(rendered.path().unwrap().exists());
+        assert_eq!(rendered.warnings.len(), 1);
+        assert_eq!(
+            rendered.warnings[0].url.as_deref(),
+            Some("http://localhost:1/logo.png")
+        );
+
+        let cmd = stand_in(
+            "page-error",
+            r#"echo 'Loading pages (1/6)' >&2
+echo 'Error: Failed loading page http://localhost:1/ (sometimes it will work just to ignore this error with --load-error-handling ignore)' >&2
+exit 1"#,
+        );
+        let pdf_app = PdfApp {
+            app: Core::new(cmd).unwrap(),
+            options: PdfOptions::default(),
+        };
+        let err = pdf_app
+            .run(WkhtmlInput::Url("http://localhost:1/"), "demo")
+            .unwrap_err();
+        assert!(matches!(&err, WkhtmlError::NonZeroExit { warnings, .. } if warnings.len() == 1));
+        assert_eq!(
+            err.to_string(),
+            "Rendering failed with exit code 1: Failed loading page http://localhost:1/ \
+(sometimes it will work just to ignore this error with --load-error-handling ignore)"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_progress() {
+        use std::sync::{Arc, Mutex};
+
+        // Waits for the first update to reach the callback before finishing, so the
+        // render fails if progress is only reported once the process exits
+        let cmd = stand_in(
+            "progress",
+            r#"for a in "$@"; do out="$a"; done
+rm -f "$0.seen"
+printf 'Loading pages (1/6)\n[=====>      ] 50%%\r[============] 100%%\r' >&2
+i=0
+while [ ! -f "$0.seen" ] && [ $i -lt 50 ]; do sleep 0.1; i=$((i+1)); done
+[ -f "$0.seen" ] || exit 3
+printf 'Warning: Failed to load file:///x.png (ignore)\nPrinting pages (6/6)\nDone\n' >&2
+echo pdf > "$out""#,
+        );
+        let seen = format!("{}.seen", cmd);
+        let updates = Arc::new(Mutex::new(Vec::new()));
+        let mut pdf_app = PdfApp {
+            app: Core::new(cmd).unwrap(),
+            options: PdfOptions::default(),
+        };
+        let recorded = updates.clone();
+        pdf_app
+            .on_progress(move |progress| {
+                let _ = fs::write(&seen, "");
+                recorded.lock().unwrap().push(progress.clone());
+            })
+            .unwrap();
+        let res = pdf_app.run(WkhtmlInput::File("examples/index.html"), "demo");
+        assert!(res.is_ok(), "{}", res.unwrap_err());
+
+        let updates = updates.lock().unwrap();
+        let updates: Vec<(&str, u32, Option<u8>)> = updates
+            .iter()
+            .map(|progress| (progress.phase.as_str(), progress.step, progress.percent))
+            .collect();
+        assert_eq!(
+            updates,
+            [
+                ("Loading pages", 1, None),
+                ("Loading pages", 1, Some(50)),
+                ("Loading pages", 1, Some(100)),
+                ("Printing pages", 6, None),
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_render_output() {
+        let cmd = stand_in(
+            "two-pages",
+            r#"for a in "$@"; do out="$a"; done
+echo 'Warning: Failed to load file:///x.png (ignore)' >&2
+pdf='%PDF-1.4
+1 0 obj <</Type /Pages /Kids [2 0 R 3 0 R] /Count 2>> endobj
+2 0 obj <</Type /Page /Parent 1 0 R>> endobj
+3 0 obj <</Type/Page /Parent 1 0 R>> endobj
+%%EOF'
+if [ "$out" = "-" ]; then echo "$pdf"; else echo "$pdf" > "$out"; fi"#,
+        );
+        let mut pdf_app = PdfApp {
+            app: Core::new(cmd.clone()).unwrap(),
+            options: PdfOptions::default(),
+        };
+        pdf_app.set_arg("grayscale", "true").unwrap();
+
+        let output = pdf_app
+            .run(WkhtmlInput::File("examples/index.html"), "report")
+            .unwrap();
+        let path = output.path().unwrap();
+        assert_eq!(output.size, fs::metadata(path).unwrap().len());
+        assert_eq!(output.page_count, Some(2));
+        assert_eq!(output.exit_code, Some(0));
+        assert_eq!(output.warnings.len(), 1);
+        assert_eq!(
+            output.command_line,
+            [
+                cmd.as_str(),
+                "--grayscale",
+                "examples/index.html",
+                path.to_str().unwrap()
+            ]
+        );
+        assert!(output.duration > Duration::ZERO);
+
+        let request = RenderRequest::new(Vec::new(), WkhtmlInput::File("examples/index.html"));
+        let output = pdf_app.app.render_to_bytes(request).unwrap();
+        assert_eq!(output.page_count, Some(2));
+        assert_eq!(output.size, output.bytes().unwrap().len() as u64);
+    }
+
+    #[test]
+    fn test_output_cleanup() {
+        let pdf_app = PdfApp::with_renderer(MockRenderer::new());
+        let input = WkhtmlInput::Html("<p>DEMO</p>");
+
+        let output = pdf_app.run(input.clone(), "dropped").unwrap();
+        let path = output.path().unwrap().to_path_buf();
+        assert!(path.exists());
+        drop(output);
+        assert!(!path.exists());
+
+        let kept = pdf_app.run(input.clone(), "kept").unwrap().keep().unwrap();
+        assert!(kept.exists());
+        fs::remove_file(kept).unwrap();
+
+        let to = std::env::temp_dir().join("wkhtmlapp-persisted.pdf");
+        let output = pdf_app.run(input.clone(), "persisted").unwrap();
+        let from = output.path().unwrap().to_path_buf();
+        assert_eq!(output.persist(&to).unwrap(), to);
+        assert!(!from.exists());
+        assert_eq!(fs::read(&to).unwrap(), b"%PDF-1.4\n%mock\n%%EOF\n");
+        fs::remove_file(to).unwrap();
+
+        // A failed persist hands the output back instead of deleting it
+        let to = std::env::temp_dir().join("wkhtmlapp-missing-dir/persisted.pdf");
+        let output = pdf_app.run(input.clone(), "unpersisted").unwrap();
+        let err = output.persist(&to).unwrap_err();
+        let path = err.output.path().unwrap().to_path_buf();
+        assert!(path.exists());
+        let err = (*err.output).into_file().unwrap().persist(&to).unwrap_err();
+        assert!(path.exists());
+        drop(err);
+        assert!(!path.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_work_dir_gc() {
+        use std::time::SystemTime;
+
+        let work_dir = std::env::temp_dir().join("wkhtmlapp-tests/gc");
+        let _ = fs::remove_dir_all(&work_dir);
+        let mut core = Core::new(stand_in("gc-tool", "exit 0")).unwrap();
+        core.set_work_dir(work_dir.to_str().unwrap()).unwrap();
+        let create = |name: &str, size: usize, age_secs: u64| {
+            let path = work_dir.join(name);
+            let file = fs::File::create(&path).unwrap();
+            file.set_len(size as u64).unwrap();
+            file.set_modified(SystemTime::now() - Duration::from_secs(age_secs))
+                .unwrap();
+            path
+        };
+        let old = create(&format!("{}-old.pdf", uuid::Uuid::new_v4()), 10, 7200);
+        let older = create(&format!("{}-older.pdf", uuid::Uuid::new_v4()), 100, 1800);
+        let recent = create(&format!("{}-recent.pdf", uuid::Uuid::new_v4()), 100, 60);
+        // Not created by `get_out_path`, never touched
+        let foreign = create("report-old.pdf", 1000, 7200);
+
+        let report = core
+            .collect_garbage(&GcPolicy {
+                max_age: Some(Duration::from_secs(3600)),
+                max_size: None,
+            })
+            .unwrap();
+        assert_eq!(
+            report,
+            GcReport {
+                removed: 1,
+                freed: 10,
+                remaining: 200
+            }
+        );
+        assert!(!old.exists());
+
+        let report = core
+            .collect_garbage(&GcPolicy {
+                max_age: None,
+                max_size: Some(150),
+            })
+            .unwrap();
+        assert_eq!(report.removed, 1);
+        assert!(!older.exists());
+        assert!(recent.exists());
+        assert!(foreign.exists());
+
+        let gc = core.spawn_gc(
+            GcPolicy {
+                max_age: Some(Duration::from_secs(30)),
+                max_size: None,
+            },
+            Duration::from_millis(20),
+        );
+        std::thread::sleep(Duration::from_millis(300));
+        gc.stop();
+        assert!(!recent.exists());
+        assert!(foreign.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_to_path() {
+        // Fails when asked to, leaving a partial file behind
+        let cmd = stand_in(
+            "to-path",
+            r#"for a in "$@"; do out="$a"; done
+case "$out" in */.*-INV-001.pdf) ;; *) exit 4 ;; esac
+echo "$1" > "$out"
+[ "$1" = "--grayscale" ] && exit 1
+exit 0"#,
+        );
+        let dir = std::env::temp_dir().join("wkhtmlapp-tests/invoices");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("INV-001.pdf");
+        fs::write(&path, "previous\n").unwrap();
+        let mut pdf_app = PdfApp {
+            app: Core::new(cmd).unwrap(),
+            options: PdfOptions::default(),
+        };
+        pdf_app.set_arg("title", "Invoice").unwrap();
+
+        let output = pdf_app
+            .run_to_path(WkhtmlInput::File("examples/index.html"), &path)
+            .unwrap();
+        assert_eq!(output.path(), Some(path.as_path()));
+        drop(output);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "--title\n");
+
+        // A failed render leaves the previous file and no temporary file
+        pdf_app.set_arg("grayscale", "true").unwrap();
+        let res = pdf_app.run_to_path(WkhtmlInput::File("examples/index.html"), &path);
+        assert!(matches!(res, Err(WkhtmlError::NonZeroExit { .. })));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "--title\n");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_render_pool() {
+        let cmd = stand_in(
+            "slow-pool",
+            r#"for a in "$@"; do out="$a"; done
+sleep 0.4
+echo pdf > "$out""#,
+        );
+        let pool = RenderPool::new(PoolConfig {
+            max_concurrency: 1,
+            max_queue: 1,
+            queue_timeout: Some(Duration::from_millis(150)),
+        });
+        let mut pdf_app = PdfApp {
+            app: Core::new(cmd).unwrap(),
+            options: PdfOptions::default(),
+        };
+        pdf_app.set_pool(&pool).unwrap();
+        let render = |pdf_app: &PdfApp| {
+            pdf_app
+                .run(WkhtmlInput::File("examples/index.html"), "pooled")
+                .map(|_| ())
+        };
+
+        std::thread::scope(|scope| {
+            let running = scope.spawn(|| render(&pdf_app));
+            std::thread::sleep(Duration::from_millis(100));
+            let queued = scope.spawn(|| render(&pdf_app));
+            std::thread::sleep(Duration::from_millis(50));
+            assert_eq!((pool.stats().running, pool.stats().queued), (1, 1));
+
+            assert!(matches!(
+                render(&pdf_app),
+                Err(WkhtmlError::QueueFull { max_queue: 1 })
+            ));
+            assert!(matches!(
+                queued.join().unwrap(),
+                Err(WkhtmlError::QueueTimeout { .. })
+            ));
+            assert!(running.join().unwrap().is_ok());
+        });
+        assert_eq!(
+            pool.stats(),
+            PoolStats {
+                completed: 1,
+                rejected: 1,
+                timed_out: 1,
+                ..Default::default()
+            }
+        );
+
+        // Without a queue timeout the queued renders run one after the other, a
+        // pool without any slot is given one instead of waiting forever
+        let pool = RenderPool::new(PoolConfig {
+            max_concurrency: 0,
+            max_queue: 4,
+            queue_timeout: None,
+        });
+        assert_eq!(pool.config().max_concurrency, 1);
+        pdf_app.set_pool(&pool).unwrap();
+        std::thread::scope(|scope| {
+            let renders: Vec<_> = (0..3).map(|_| scope.spawn(|| render(&pdf_app))).collect();
+            for render in renders {
+                assert!(render.join().unwrap().is_ok());
+            }
+        });
+        assert_eq!(pool.stats().completed, 3);
+    }
+
+    #[cfg(all(unix, feature = "tokio"))]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_render_pool_async() {
+        let cmd = stand_in(
+            "slow-pool-async",
+            r#"for a in "$@"; do out="$a"; done
+sleep 0.3
+echo pdf > "$out""#,
+        );
+        let pool = RenderPool::new(PoolConfig {
+            max_concurrency: 1,
+            max_queue: 4,
+            queue_timeout: None,
+        });
+        let mut pdf_app = PdfApp {
+            app: Core::new(cmd).unwrap(),
+            options: PdfOptions::default(),
+        };
+        pdf_app.set_pool(&pool).unwrap();
+
+        // A blocking render holds the only slot
+        let blocking = {
+            let pdf_app = pdf_app.clone();
+            std::thread::spawn(move || {
+                pdf_app
+                    .run(WkhtmlInput::File("examples/index.html"), "blocking")
+                    .map(|_| ())
+            })
+        };
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let input = WkhtmlInput::File("examples/index.html");
+        let cancelled = pdf_app.run_async(input.clone(), "cancelled");
+        assert!(tokio::time::timeout(Duration::from_millis(50), cancelled)
+            .await
+            .is_err());
+        assert_eq!(pool.stats().queued, 0);
+
+        let (first, second) = tokio::join!(
+            pdf_app.run_async(input.clone(), "first"),
+            pdf_app.run_async(input.clone(), "second")
+        );
+        assert!(first.is_ok() && second.is_ok());
+        assert!(blocking.join().unwrap().is_ok());
+        assert_eq!(pool.stats().completed, 3);
+        assert_eq!(pool.stats().running, 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_render_batch() {
+        let cmd = stand_in(
+            "batch",
+            r#"for a in "$@"; do out="$a"; done
+case "$*" in *missing.html*) exit 1 ;; esac
+echo "$@" > "$out""#,
+        );
+        let pdf_app = PdfApp {
+            app: Core::new(cmd).unwrap(),
+            options: PdfOptions::default(),
+        };
+        let grayscale = PdfOptions {
+            grayscale: true,
+            ..Default::default()
+        };
+        let items = (0..6).map(|i| {
+            let name = format!("statement-{}", i);
+            match i {
+                2 => BatchItem::new(WkhtmlInput::File("missing.html"), &name),
+                4 => BatchItem::with_options(
+                    WkhtmlInput::File("examples/index.html"),
+                    &name,
+                    grayscale.clone(),
+                ),
+                _ => BatchItem::new(WkhtmlInput::File("examples/index.html"), &name),
+            }
+        });
+        let config = BatchConfig {
+            parallelism: 3,
+            mode: BatchMode::ContinueOnError,
+        };
+        let report = pdf_app.render_batch(items, &config);
+        assert_eq!((report.succeeded, report.failed, report.skipped), (5, 1, 0));
+        assert!(!report.is_success());
+        for (i, result) in report.results.iter().enumerate() {
+            match result {
+                Ok(output) => {
+                    let path = output.path().unwrap();
+                    let name = format!("statement-{}.pdf", i);
+                    assert!(path.to_str().unwrap().ends_with(&name));
+                    let args = fs::read_to_string(path).unwrap();
+                    assert_eq!(args.starts_with("--grayscale"), i == 4);
+                }
+                Err(e) => {
+                    assert_eq!(i, 2);
+                    assert!(matches!(e, WkhtmlError::NonZeroExit { .. }));
+                }
+            }
+        }
+
+        let items = ["examples/index.html", "missing.html", "examples/index.html"]
+            .into_iter()
+            .map(|file| BatchItem::new(WkhtmlInput::File(file), "statement"));
+        let config = BatchConfig {
+            parallelism: 1,
+            mode: BatchMode::FailFast,
+        };
+        let report = pdf_app.render_batch(items, &config);
+        assert_eq!((report.succeeded, report.failed, report.skipped), (1, 1, 1));
+        assert!(matches!(report.results[2], Err(WkhtmlError::Skipped)));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_persistent_worker() {
+        let cmd = stand_in(
+            "worker",
+            r#"[ "$1" = "--read-args-from-stdin" ] || exit 2
+while IFS= read -r line; do
+  eval "set -- $line"
+  for a in "$@"; do out="$a"; done
+  case "$line" in
+    *crash*) exit 3 ;;
+    *missing*) echo 'Exit with code 1 due to network error: ContentNotFoundError' >&2; exit 1 ;;
+  esac
+  printf 'Loading pages (1/6)\r[====>     ] 50%%\n' >&2
+  { echo "$$"; for a in "$@"; do echo "$a"; done; } > "$out"
+  echo Done >&2
+done"#,
+        );
+        let mut pdf_app = PdfApp {
+            app: Core::new(cmd).unwrap(),
+            options: PdfOptions {
+                title: Some(r#"Q1 "final" \ report"#.to_string()),
+                quiet: true,
+                ..Default::default()
+            },
+        };
+        let steps = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = steps.clone();
+        pdf_app
+            .on_progress(move |progress| seen.lock().unwrap().push(progress.percent))
+            .unwrap();
+        let mut worker = PersistentWorker::new(&pdf_app);
+
+        let read = |output: &crate::RenderOutput| -> Vec<String> {
+            let content = fs::read_to_string(output.path().unwrap()).unwrap();
+            content.lines().map(str::to_string).collect()
+        };
+        let first = worker
+            .run(WkhtmlInput::Url("https://example.com"), "first")
+            .unwrap();
+        let lines = read(&first);
+        assert!(lines.contains(&r#"Q1 "final" \ report"#.to_string()));
+        assert!(!lines.contains(&"--quiet".to_string()));
+        assert!(lines.windows(2).any(|w| w == ["--log-level", "info"]));
+        assert!(first.path().unwrap().ends_with(lines.last().unwrap()));
+
+        let html = worker.run(WkhtmlInput::Html("<p>hi</p>"), "html").unwrap();
+        let html_lines = read(&html);
+        assert_eq!(html_lines[0], lines[0], "the process is reused");
+        let input = &html_lines[html_lines.len() - 2];
+        assert!(input.ends_with("input.html"));
+        assert!(!std::path::Path::new(input).exists());
+        assert_eq!(*steps.lock().unwrap(), [None, Some(50), None, Some(50)]);
+
+        let err = worker.run(WkhtmlInput::Url("https://missing.test"), "missing");
+        assert!(matches!(
+            err,
+            Err(WkhtmlError::NonZeroExit { code: Some(1), .. })
+        ));
+        assert_eq!(worker.restarts(), 1);
+        let err = worker.run(WkhtmlInput::Url("https://crash.test"), "crash");
+        assert!(matches!(
+            err,
+            Err(WkhtmlError::NonZeroExit { code: Some(3), .. })
+        ));
+        assert_eq!(worker.restarts(), 2);
+        let long_url = format!("https://example.com/?sig={}", "a".repeat(21_000));
+        let err = worker.run(WkhtmlInput::Url(&long_url), "long");
+        assert!(matches!(err, Err(WkhtmlError::InvalidOption { .. })));
+
+        let after = worker
+            .run(WkhtmlInput::Url("https://example.com"), "after")
+            .unwrap();
+        assert_ne!(read(&after)[0], lines[0], "a new process was started");
+        worker.shutdown();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_secure_args() {
+        let cmd = stand_in(
+            "secure-args",
+            r#"if [ "$1" = "--read-args-from-stdin" ]; then
+  [ "$#" = 1 ] || exit 2
+  IFS= read -r line
+  eval "set -- $line"
+fi
+for a in "$@"; do out="$a"; done
+for a in "$@"; do echo "$a"; done > "$out""#,
+        );
+        let mut pdf_app = PdfApp {
+            app: Core::new(cmd).unwrap(),
+            options: PdfOptions {
+                page: PageOptions {
+                    password: Some("hunter2".to_string()),
+                    cookies: vec![("session".to_string(), "s3cr3t cookie".to_string())],
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        };
+        pdf_app
+            .add_secret_arg("post", &["token", "t0k\"en"])
+            .unwrap()
+            .set_secure_args(true)
+            .unwrap();
+        let secrets = ["hunter2", "s3cr3t cookie", "token", "t0k\"en"];
+
+        let output = pdf_app
+            .run(WkhtmlInput::Html("<p>hi</p>"), "secure")
+            .unwrap();
+        let args = fs::read_to_string(output.path().unwrap()).unwrap();
+        let args: Vec<&str> = args.lines().collect();
+        for secret in secrets {
+            assert!(args.contains(&secret), "{} is passed to the tool", secret);
+            assert!(!output.command_line.iter().any(|arg| arg == secret));
+        }
+        assert!(args[args.len() - 2].ends_with("input.html"));
+        let command_line = output.command_line.join(" ");
+        assert!(command_line.contains("--password <redacted>"));
+        assert!(command_line.contains("--cookie session <redacted>"));
+
+        // The tool reads at most one buffer per line, a longer one is refused
+        let long_url = format!("https://example.com/?sig={}", "a".repeat(21_000));
+        let err = pdf_app.run(WkhtmlInput::Url(&long_url), "long");
+        assert!(matches!(err, Err(WkhtmlError::InvalidOption { .. })));
+
+        pdf_app.set_secure_args(false).unwrap();
+        let output = pdf_app
+            .run(WkhtmlInput::File("examples/index.html"), "plain")
+            .unwrap();
+        assert!(!output
+            .command_line
+            .iter()
+            .any(|arg| secrets.contains(&arg.as_str())));
+
+        let mut core = pdf_app.app.clone();
+        core.set_secure_args(true);
+        let out_path = core.get_out_path("legacy.pdf");
+        let err = core.execute(vec!["-".to_string()], Some("<p>hi</p>"), out_path);
+        assert!(matches!(err, Err(WkhtmlError::InvalidOption { .. })));
+
+        let image_dir = std::env::temp_dir().join("wkhtmlapp-tests/image");
+        fs::create_dir_all(&image_dir).unwrap();
+        let image_cmd = image_dir.join("wkhtmltoimage");
+        fs::copy(&core.wkhtmltox_cmd, &image_cmd).unwrap();
+        let mut core = Core::new(image_cmd.to_str().unwrap().to_string()).unwrap();
+        core.set_secure_args(true);
+        let out_path = core.get_out_path("image.png");
+        let err = core.execute(vec!["page.html".to_string()], None, out_path);
+        assert!(matches!(err, Err(WkhtmlError::InvalidOption { .. })));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_sandbox() {
+        let cmd = stand_in(
+            "sandbox",
+            r#"case " $* " in *" - "*) cat > /dev/null ;; esac
+for a in "$@"; do out="$a"; done
+for a in "$@"; do echo "$a"; done > "$out""#,
+        );
+        let mut pdf_app = PdfApp {
+            app: Core::new(cmd).unwrap(),
+            options: PdfOptions {
+                page: PageOptions {
+                    local_file_access: Some(true),
+                    javascript: Some(true),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        };
+        pdf_app
+            .set_sandbox(SandboxPolicy {
+                allow_paths: vec!["/srv/assets".into()],
+                allowed_file_dirs: vec!["examples".into()],
+                allowed_urls: vec!["https://example.com".to_string()],
+                ..Default::default()
+            })
+            .unwrap();
+        let args_of = |output: &crate::RenderOutput| -> Vec<String> {
+            let args = fs::read_to_string(output.path().unwrap()).unwrap();
+            args.lines().map(str::to_string).collect()
+        };
+        let position = |args: &[String], arg: &str| args.iter().rposition(|a| a == arg).unwrap();
+
+        let output = pdf_app.run(WkhtmlInput::Html("<p>hi</p>"), "html").unwrap();
+        let args = args_of(&output);
+        assert!(
+            position(&args, "--disable-local-file-access")
+                > position(&args, "--enable-local-file-access")
+        );
+        assert!(position(&args, "--disable-javascript") > position(&args, "--enable-javascript"));
+        assert!(args.contains(&"--disable-plugins".to_string()));
+        assert_eq!(args[position(&args, "--allow") + 1], "/srv/assets");
+
+        assert!(pdf_app
+            .run(WkhtmlInput::File("examples/index.html"), "file")
+            .is_ok());
+        assert!(pdf_app
+            .run(WkhtmlInput::Url("https://example.com/r?id=1"), "url")
+            .is_ok());
+        for input in [
+            WkhtmlInput::File("src/lib.rs"),
+            WkhtmlInput::File("examples/../src/lib.rs"),
+            WkhtmlInput::File("examples/missing.html"),
+            WkhtmlInput::Url("https://example.com.evil.test/"),
+            WkhtmlInput::Url("file:///etc/passwd"),
+        ] {
+            let err = pdf_app.run(input.clone(), "rejected");
+            assert!(
+                matches!(err, Err(WkhtmlError::InputNotAllowed { .. })),
+                "{:?} is rejected",
+                input
+            );
+        }
+
+        let mut document = PdfDocument::new();
+        let page = PageOptions {
+            local_file_access: Some(true),
+            ..Default::default()
+        };
+        document.page_with_options(WkhtmlInput::Html("<p>hi</p>"), page);
+        let args = args_of(&pdf_app.run_document(&document, "document").unwrap());
+        assert!(
+            position(&args, "--disable-local-file-access")
+                > position(&args, "--enable-local-file-access")
+        );
+        document.page(WkhtmlInput::File("/etc/passwd"));
+        let err = pdf_app.run_document(&document, "document");
+        assert!(matches!(err, Err(WkhtmlError::InputNotAllowed { .. })));
+
+        // The sandbox applies to the page, not only to the cover and toc before it
+        pdf_app.options.cover = Some("examples/index.html".to_string());
+        pdf_app.options.toc = Some(TocOptions::default());
+        let args = args_of(
+            &pdf_app
+                .run(WkhtmlInput::Html("<p>hi</p>"), "cover")
+                .unwrap(),
+        );
+        let first = |arg: &str| args.iter().position(|a| a == arg).unwrap();
+        assert!(first("--disable-local-file-access") < first("cover"));
+        assert!(first("--disable-javascript") < first("toc"));
+        assert!(position(&args, "--disable-local-file-access") > first("cover"));
+        assert_eq!(args[first("cover") + 1], "examples/index.html");
+
+        pdf_app.options.cover = Some("/etc/passwd".to_string());
+        let err = pdf_app.run(WkhtmlInput::Html("<p>hi</p>"), "cover");
+        assert!(matches!(err, Err(WkhtmlError::InputNotAllowed { .. })));
+        pdf_app.options.cover = None;
+        pdf_app.set_arg("cover", "file:///etc/passwd").unwrap();
+        let err = pdf_app.run(WkhtmlInput::Html("<p>hi</p>"), "cover");
+        assert!(matches!(err, Err(WkhtmlError::InputNotAllowed { .. })));
+        let raw = vec!["cover".to_string(), "/etc/passwd".to_string()];
+        let err = pdf_app.app.run_with_html("<p>hi</p>", "raw", raw);
+        assert!(matches!(err, Err(WkhtmlError::InvalidOption { .. })));
+        let raw = ["--grayscale", "toc", "--zoom", "2"]
+            .map(String::from)
+            .to_vec();
+        let err = pdf_app.app.run_with_html("<p>hi</p>", "raw", raw);
+        assert!(matches!(err, Err(WkhtmlError::InvalidOption { .. })));
+
+        // Option values that happen to be keywords are not objects
+        pdf_app.options.extra.clear();
+        pdf_app.options.header_footer.header_left = Some("page".to_string());
+        pdf_app.set_arg("title", "cover").unwrap();
+        let args = args_of(
+            &pdf_app
+                .run(WkhtmlInput::Html("<p>hi</p>"), "values")
+                .unwrap(),
+        );
+        assert_eq!(args[position(&args, "--title") + 1], "cover");
+        let raw = ["--title", "toc", "--grayscale"].map(String::from).to_vec();
+        assert!(pdf_app.app.run_with_html("<p>hi</p>", "raw", raw).is_ok());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_url_policy() {
+        use std::io::{Read, Write};
+        use std::net::{IpAddr, TcpListener};
+
+        let range: IpRange = "10.0.0.0/8".parse().unwrap();
+        assert!(range.contains("10.1.2.3".parse().unwrap()));
+        assert!(!range.contains("11.0.0.1".parse().unwrap()));
+        assert!(!range.contains("::1".parse().unwrap()));
+        let mapped: IpAddr = "::ffff:10.0.0.1".parse().unwrap();
+        assert!(range.contains(mapped));
+        assert!("0.0.0.0/0"
+            .parse::<IpRange>()
+            .unwrap()
+            .contains("8.8.8.8".parse().unwrap()));
+        assert!("10.0.0.0/33".parse::<IpRange>().is_err());
+        assert!("example.com/8".parse::<IpRange>().is_err());
+
+        // Stand-in web server answering the two URLs that pass the policy
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            for stream in listener.incoming().take(2) {
+                let mut stream = stream.unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let read = stream.read(&mut buf).unwrap();
+                    if read == 0 {
+                        break;
+                    }
+                    request.extend_from_slice(&buf[..read]);
+                }
+                let body = "%PDF-1.4\n%stand-in\n%%EOF\n";
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+
+        let cmd = stand_in(
+            "url-policy",
+            r#"for a in "$@"; do url="$out"; out="$a"; done
+echo "$url" > "$out""#,
+        );
+        let mut pdf_app = PdfApp {
+            app: Core::new(cmd).unwrap(),
+            options: PdfOptions::default(),
+        };
+        pdf_app
+            .set_url_policy(UrlPolicy {
+                allowed_hosts: vec!["*.example.com".to_string()],
+                allowed_networks: vec!["127.0.0.0/8".parse().unwrap(), "::1".parse().unwrap()],
+                ..Default::default()
+            })
+            .unwrap();
+
+        for url in [
+            format!("http://127.0.0.1:{}/report", port),
+            format!("http://LOCALHOST:{}/report?id=1", port),
+        ] {
+            let output = pdf_app.run(WkhtmlInput::Url(&url), "url").unwrap();
+            let passed = fs::read_to_string(output.path().unwrap()).unwrap();
+            assert_eq!(passed.trim_end(), url);
+            // Fetches it as the tool would, from the local server
+            let rest = url.strip_prefix("http://").unwrap();
+            let (authority, path) = rest.split_at(rest.find('/').unwrap());
+            let mut stream = std::net::TcpStream::connect(authority).unwrap();
+            write!(
+                stream,
+                "GET {} HTTP/1.1\r\nHost: {}\r\n\r\n",
+                path, authority
+            )
+            .unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            assert!(response.ends_with("%PDF-1.4\n%stand-in\n%%EOF\n"));
+        }
+        server.join().unwrap();
+
+        for url in [
+            "http://169.254.169.254/latest/meta-data/".to_string(),
+            "http://user@169.254.169.254/".to_string(),
+            "http://[::ffff:169.254.169.254]/".to_string(),
+            format!("http://10.0.0.1:{}/", port),
+            format!("ftp://127.0.0.1:{}/", port),
+            "file:///etc/passwd".to_string(),
+            "--enable-local-file-access".to_string(),
+            "http://127.0.0.1%2f@169.254.169.254/".to_string(),
+            "http:///etc/passwd".to_string(),
+            r"http://allowed.example.com\@169.254.169.254/latest/meta-data/".to_string(),
+            "http://a@b@169.254.169.254/".to_string(),
+        ] {
+            let err = pdf_app.run(WkhtmlInput::Url(&url), "rejected");
+            assert!(
+                matches!(err, Err(WkhtmlError::UrlRejected { .. })),
+                "{} is rejected",
+                url
+            );
+        }
+
+        // Covers are inputs too
+        let html = WkhtmlInput::Html("<p>hi</p>");
+        pdf_app.options.cover = Some("http://169.254.169.254/".to_string());
+        let err = pdf_app.run(html.clone(), "cover");
+        assert!(matches!(err, Err(WkhtmlError::UrlRejected { .. })));
+        pdf_app.options.cover = None;
+        pdf_app
+            .set_arg("cover", "http://169.254.169.254/latest/meta-data/")
+            .unwrap();
+        let err = pdf_app.run(html, "cover");
+        assert!(matches!(err, Err(WkhtmlError::UrlRejected { .. })));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_argument_injection() {
+        let cmd = stand_in(
+            "injection",
+            r#"case " $* " in *" - "*) cat > /dev/null ;; esac
+for a in "$@"; do out="$a"; done
+for a in "$@"; do echo "$a"; done > "$out""#,
+        );
+        let pdf_app = PdfApp {
+            app: Core::new(cmd).unwrap(),
+            options: PdfOptions {
+                cover: Some("-cover.html".to_string()),
+                ..Default::default()
+            },
+        };
+        let work_dir = pdf_app.app.work_dir.clone();
+
+        let output = pdf_app
+            .run(WkhtmlInput::File("-rf.html"), "-report")
+            .unwrap();
+        let path = output.path().unwrap();
+        assert_eq!(path.parent().unwrap(), work_dir);
+        let args = fs::read_to_string(path).unwrap();
+        let args: Vec<&str> = args.lines().collect();
+        assert!(args.contains(&"./-rf.html"));
+        assert!(args.contains(&"./-cover.html"));
+        assert!(!args
+            .iter()
+            .any(|arg| *arg == "-rf.html" || *arg == "-cover.html"));
+
+        for name in ["../escape", "a/b", "a\\b", "line\nbreak"] {
+            let err = pdf_app.run(WkhtmlInput::Html("<p>hi</p>"), name);
+            assert!(
+                matches!(err, Err(WkhtmlError::InvalidName { .. })),
+                "{:?} is rejected",
+                name
+            );
+        }
+        for name in ["..", ".", ""] {
+            let err = pdf_app.app.run_with_html("<p>hi</p>", name, Vec::new());
+            assert!(matches!(err, Err(WkhtmlError::InvalidName { .. })));
+        }
+        let mock = PdfApp::with_renderer(MockRenderer::new());
+        let err = mock.run(WkhtmlInput::Html("<p>hi</p>"), "../../etc/cron.d/job");
+        assert!(matches!(err, Err(WkhtmlError::InvalidName { .. })));
+        let path = pdf_app.app.get_out_path("../../escape.pdf");
+        assert_eq!(path.parent().unwrap(), work_dir);
+
+        for url in [
+            "--enable-local-file-access",
+            "-",
+            "http://example.com/\n--x",
+        ] {
+            let err = pdf_app.run(WkhtmlInput::Url(url), "url");
+            assert!(
+                matches!(err, Err(WkhtmlError::UrlRejected { .. })),
+                "{:?}",
+                url
+            );
+        }
+        for path in ["", "report.html\n--enable-local-file-access"] {
+            let err = pdf_app.run(WkhtmlInput::File(path), "file");
+            assert!(
+                matches!(err, Err(WkhtmlError::InputNotAllowed { .. })),
+                "{:?}",
+                path
+            );
+        }
+    }
 }
@@ -0,0 +1,175 @@
+use crate::app::{WkhtmlError, WkhtmlInput};
+use crate::core::Core;
+use crate::output::{OutputData, OutputPath, RenderOutput};
+use crate::renderer::{RenderRequest, Renderer};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use std::{env, fs};
+use uuid::Uuid;
+
+const MOCK_OUTPUT: &[u8] = b"%PDF-1.4\n%mock\n%%EOF\n";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordedInput {
+    File(String),
+    Url(String),
+    Html(String),
+}
+
+impl From<&WkhtmlInput<'_>> for RecordedInput {
+    fn from(input: &WkhtmlInput<'_>) -> Self {
+        match input {
+            WkhtmlInput::File(path) => RecordedInput::File(path.to_string()),
+            WkhtmlInput::Url(url) => RecordedInput::Url(url.to_string()),
+            WkhtmlInput::Html(html) => RecordedInput::Html(html.to_string()),
+        }
+    }
+}
+
+/// A request received by a `MockRenderer`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordedRender {
+    /// The options and object keywords, in command line order, without the inputs.
+    pub args: Vec<String>,
+    pub inputs: Vec<RecordedInput>,
+    /// Output file name, `None` for in-memory and writer renders.
+    pub name: Option<String>,
+}
+
+#[derive(Debug)]
+struct MockState {
+    output: Result<Vec<u8>, WkhtmlError>,
+    renders: Vec<RecordedRender>,
+}
+
+/// `Renderer` that records every request and answers with canned bytes or an
+/// error, so apps can be tested without wkhtmltopdf installed.
+///
+/// Clones share their recordings, keep one to inspect the renders of an app.
+#[derive(Debug, Clone)]
+pub struct MockRenderer {
+    state: Arc<Mutex<MockState>>,
+    work_dir: PathBuf,
+}
+
+impl Default for MockRenderer {
+    fn default() -> Self {
+        Self::returning(MOCK_OUTPUT)
+    }
+}
+
+impl MockRenderer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn returning(bytes: impl Into<Vec<u8>>) -> Self {
+        Self::with_output(Ok(bytes.into()))
+    }
+
+    pub fn failing(error: WkhtmlError) -> Self {
+        Self::with_output(Err(error))
+    }
+
+    fn with_output(output: Result<Vec<u8>, WkhtmlError>) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(MockState {
+                output,
+                renders: Vec::new(),
+            })),
+            work_dir: env::temp_dir().join("wkhtmlapp-mock"),
+        }
+    }
+
+    pub fn renders(&self) -> Vec<RecordedRender> {
+        self.state().renders.clone()
+    }
+
+    pub fn last_render(&self) -> Option<RecordedRender> {
+        self.state().renders.last().cloned()
+    }
+
+    fn state(&self) -> MutexGuard<'_, MockState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn output(request: &RenderRequest, data: OutputData) -> RenderOutput {
+        let mut command_line = vec!["mock".to_string()];
+        command_line.extend(Self::args(request));
+        let mut output = RenderOutput {
+            data,
+            size: 0,
+            duration: Duration::ZERO,
+            exit_code: Some(0),
+            warnings: Vec::new(),
+            command_line,
+            page_count: None,
+        };
+        output.measure();
+        output
+    }
+
+    fn args(request: &RenderRequest) -> Vec<String> {
+        let mut args = request.args.clone();
+        for object in &request.objects {
+            args.extend(object.keyword.map(str::to_string));
+            args.extend(object.args.iter().cloned());
+        }
+        args
+    }
+
+    fn record(&self, request: &RenderRequest, name: Option<&str>) -> Result<Vec<u8>, WkhtmlError> {
+        let mut state = self.state();
+        state.renders.push(RecordedRender {
+            args: Self::args(request),
+            inputs: request.inputs().map(RecordedInput::from).collect(),
+            name: name.map(str::to_string),
+        });
+        state.output.clone()
+    }
+}
+
+impl Renderer for MockRenderer {
+    fn render_to_file(
+        &self,
+        request: RenderRequest<'_>,
+        name: &str,
+    ) -> Result<RenderOutput, WkhtmlError> {
+        Core::check_name(name)?;
+        let bytes = self.record(&request, Some(name))?;
+        let path = self.work_dir.join(format!("{}-{}", Uuid::new_v4(), name));
+        fs::create_dir_all(&self.work_dir)
+            .and_then(|_| fs::write(&path, bytes))
+            .map_err(|e| WkhtmlError::io("Failed to write mock output", e))?;
+        Ok(Self::output(
+            &request,
+            OutputData::File(OutputPath::new(path)),
+        ))
+    }
+
+    fn render_to_path(
+        &self,
+        request: RenderRequest<'_>,
+        path: &Path,
+    ) -> Result<RenderOutput, WkhtmlError> {
+        let bytes = self.record(&request, Some(&path.to_string_lossy()))?;
+        fs::write(path, bytes).map_err(|e| WkhtmlError::io("Failed to write mock output", e))?;
+        Ok(Self::output(&request, OutputData::Path(path.to_path_buf())))
+    }
+
+    fn render_to_writer(
+        &self,
+        request: RenderRequest<'_>,
+        writer: &mut dyn Write,
+    ) -> Result<RenderOutput, WkhtmlError> {
+        let bytes = self.record(&request, None)?;
+        writer
+            .write_all(&bytes)
+            .map_err(|e| WkhtmlError::Writer(Arc::new(e)))?;
+        let mut output = Self::output(&request, OutputData::Written);
+        output.size = bytes.len() as u64;
+        Ok(output)
+    }
+}
@@ -0,0 +1,252 @@
+use crate::app::WkhtmlError;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Orientation {
+    Portrait,
+    Landscape,
+}
+
+impl fmt::Display for Orientation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Orientation::Portrait => write!(f, "Portrait"),
+            Orientation::Landscape => write!(f, "Landscape"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageSize {
+    A0,
+    A1,
+    A2,
+    A3,
+    A4,
+    A5,
+    A6,
+    A7,
+    A8,
+    A9,
+    B0,
+    B1,
+    B2,
+    B3,
+    B4,
+    B5,
+    B6,
+    B7,
+    B8,
+    B9,
+    B10,
+    C5E,
+    Comm10E,
+    DLE,
+    Executive,
+    Folio,
+    Ledger,
+    Legal,
+    Letter,
+    Tabloid,
+}
+
+impl fmt::Display for PageSize {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // wkhtmltopdf expects the QPrinter names, which match the variant names
+        write!(f, "{:?}", self)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogLevel {
+    None,
+    Error,
+    Warn,
+    Info,
+}
+
+impl fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LogLevel::None => write!(f, "none"),
+            LogLevel::Error => write!(f, "error"),
+            LogLevel::Warn => write!(f, "warn"),
+            LogLevel::Info => write!(f, "info"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadErrorHandling {
+    Abort,
+    Ignore,
+    Skip,
+}
+
+impl fmt::Display for LoadErrorHandling {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadErrorHandling::Abort => write!(f, "abort"),
+            LoadErrorHandling::Ignore => write!(f, "ignore"),
+            LoadErrorHandling::Skip => write!(f, "skip"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Pdf,
+    Ps,
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OutputFormat::Pdf => write!(f, "pdf"),
+            OutputFormat::Ps => write!(f, "ps"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unit {
+    Mm,
+    Cm,
+    In,
+    Pt,
+    Pc,
+    Px,
+}
+
+impl fmt::Display for Unit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Unit::Mm => write!(f, "mm"),
+            Unit::Cm => write!(f, "cm"),
+            Unit::In => write!(f, "in"),
+            Unit::Pt => write!(f, "pt"),
+            Unit::Pc => write!(f, "pc"),
+            Unit::Px => write!(f, "px"),
+        }
+    }
+}
+
+/// A length as accepted by the margin and page size options, e.g. `10mm`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Length {
+    pub value: f32,
+    pub unit: Unit,
+}
+
+impl Length {
+    pub fn new(value: f32, unit: Unit) -> Self {
+        Self { value, unit }
+    }
+
+    pub fn mm(value: f32) -> Self {
+        Self::new(value, Unit::Mm)
+    }
+
+    pub fn cm(value: f32) -> Self {
+        Self::new(value, Unit::Cm)
+    }
+
+    pub fn inch(value: f32) -> Self {
+        Self::new(value, Unit::In)
+    }
+
+    pub fn pt(value: f32) -> Self {
+        Self::new(value, Unit::Pt)
+    }
+
+    pub fn px(value: f32) -> Self {
+        Self::new(value, Unit::Px)
+    }
+}
+
+impl fmt::Display for Length {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", self.value, self.unit)
+    }
+}
+
+pub(crate) fn push_flag(args: &mut Vec<String>, key: &str, enabled: bool) {
+    if enabled {
+        args.push(format!("--{}", key));
+    }
+}
+
+pub(crate) fn push_value<T: fmt::Display>(args: &mut Vec<String>, key: &str, value: &Option<T>) {
+    if let Some(value) = value {
+        args.push(format!("--{}", key));
+        args.push(value.to_string());
+    }
+}
+
+pub(crate) fn push_toggle(args: &mut Vec<String>, on: &str, off: &str, value: Option<bool>) {
+    match value {
+        Some(true) => args.push(format!("--{}", on)),
+        Some(false) => args.push(format!("--{}", off)),
+        None => {}
+    }
+}
+
+pub(crate) fn push_repeated(args: &mut Vec<String>, key: &str, values: &[String]) {
+    for value in values {
+        args.push(format!("--{}", key));
+        args.push(value.clone());
+    }
+}
+
+pub(crate) fn push_pairs(args: &mut Vec<String>, key: &str, pairs: &[(String, String)]) {
+    for (name, value) in pairs {
+        args.push(format!("--{}", key));
+        args.push(name.clone());
+        args.push(value.clone());
+    }
+}
+
+/// Number of values following an option of wkhtmltopdf or wkhtmltoimage on
+/// the command line, 0 for switches and unknown options.
+pub(crate) fn option_arity(key: &str) -> usize {
+    match key {
+        "cookie" | "custom-header" | "post" | "post-file" | "replace" => 2,
+        // Global and outline options
+        "cookie-jar" | "copies" | "dpi" | "image-dpi" | "image-quality" | "log-level"
+        | "margin-bottom" | "margin-left" | "margin-right" | "margin-top" | "orientation"
+        | "page-height" | "page-size" | "page-width" | "title" | "dump-outline"
+        | "outline-depth" | "output-format"
+        // Page options
+        | "allow" | "bypass-proxy-for" | "cache-dir" | "checkbox-checked-svg"
+        | "checkbox-svg" | "encoding" | "javascript-delay" | "load-error-handling"
+        | "load-media-error-handling" | "minimum-font-size" | "page-offset" | "password"
+        | "proxy" | "radiobutton-checked-svg" | "radiobutton-svg" | "redirect-delay"
+        | "run-script" | "ssl-crt-path" | "ssl-key-password" | "ssl-key-path"
+        | "user-style-sheet" | "username" | "viewport-size" | "window-status" | "zoom"
+        // Header and footer options
+        | "footer-center" | "footer-font-name" | "footer-font-size" | "footer-html"
+        | "footer-left" | "footer-right" | "footer-spacing" | "header-center"
+        | "header-font-name" | "header-font-size" | "header-html" | "header-left"
+        | "header-right" | "header-spacing"
+        // TOC options
+        | "toc-depth" | "toc-font-name" | "toc-l1-font-size" | "toc-header-text"
+        | "toc-header-font-name" | "toc-header-font-size" | "toc-level-indentation"
+        | "toc-text-size-shrink" | "xsl-style-sheet"
+        // wkhtmltoimage options
+        | "crop-h" | "crop-w" | "crop-x" | "crop-y" | "format" | "height" | "width"
+        | "quality" => 1,
+        _ => 0,
+    }
+}
+
+/// Checks that options taking a `<name> <value>` pair get exactly two values.
+pub(crate) fn check_values(key: &str, values: &[&str]) -> Result<(), WkhtmlError> {
+    let pair = option_arity(key) == 2;
+    if pair && values.len() != 2 {
+        return Err(WkhtmlError::invalid_option(
+            key,
+            format!("takes a name and a value, got {} value(s)", values.len()),
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,239 @@
+use crate::app::WkhtmlError;
+use crate::warnings::RenderWarning;
+use std::fs;
+use std::io::Read;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// A rendered file in the work dir, deleted when dropped unless `keep` or
+/// `persist` is called.
+#[derive(Debug, PartialEq, Eq)]
+pub struct OutputPath {
+    path: PathBuf,
+    kept: bool,
+}
+
+impl OutputPath {
+    pub(crate) fn new(path: PathBuf) -> Self {
+        Self { path, kept: false }
+    }
+
+    /// Leaves the file in place and returns its path, the caller owns it from now on.
+    ///
+    /// The file keeps its `<uuid>-name` name in the work dir, so a work dir
+    /// collection still removes it once it is too old or the quota is exceeded.
+    /// Use `persist` to move it out of reach.
+    pub fn keep(mut self) -> PathBuf {
+        self.kept = true;
+        std::mem::take(&mut self.path)
+    }
+
+    /// Moves the file to `to` and returns its new path. On failure the file is
+    /// handed back in the error, still in place.
+    pub fn persist(self, to: impl AsRef<Path>) -> Result<PathBuf, PersistError<OutputPath>> {
+        let to = to.as_ref();
+        if fs::rename(&self.path, to).is_err() {
+            // rename can't cross file systems, fall back to a copy
+            if let Err(e) = fs::copy(&self.path, to) {
+                return Err(PersistError {
+                    error: WkhtmlError::io("Failed to persist output", e),
+                    output: Box::new(self),
+                });
+            }
+        }
+        Ok(to.to_path_buf())
+    }
+}
+
+/// A failed `persist`, with the output it was called on so it is not lost.
+#[derive(Debug)]
+pub struct PersistError<T> {
+    pub error: WkhtmlError,
+    pub output: Box<T>,
+}
+
+impl<T> std::fmt::Display for PersistError<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.error.fmt(f)
+    }
+}
+
+impl<T: std::fmt::Debug> std::error::Error for PersistError<T> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// Drops the output, removing its file, to keep using `?` in functions
+/// returning `WkhtmlError`.
+impl<T> From<PersistError<T>> for WkhtmlError {
+    fn from(err: PersistError<T>) -> Self {
+        err.error
+    }
+}
+
+impl Deref for OutputPath {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl AsRef<Path> for OutputPath {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for OutputPath {
+    fn drop(&mut self) {
+        if !self.kept {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Where the rendered document ended up.
+#[derive(Debug, PartialEq, Eq)]
+pub enum OutputData {
+    File(OutputPath),
+    /// A file at a path chosen by the caller, which is never removed.
+    Path(PathBuf),
+    Bytes(Vec<u8>),
+    /// Copied into a caller-supplied writer.
+    Written,
+}
+
+/// The result of a successful render.
+#[derive(Debug)]
+pub struct RenderOutput {
+    pub data: OutputData,
+    /// Size of the document in bytes.
+    pub size: u64,
+    /// Wall-clock time from spawning the tool to its exit.
+    pub duration: Duration,
+    pub exit_code: Option<i32>,
+    pub warnings: Vec<RenderWarning>,
+    /// The program followed by its arguments, as it was run.
+    pub command_line: Vec<String>,
+    /// Number of pages, for PDF output only.
+    pub page_count: Option<u32>,
+}
+
+impl RenderOutput {
+    pub fn path(&self) -> Option<&Path> {
+        match &self.data {
+            OutputData::File(path) => Some(path),
+            OutputData::Path(path) => Some(path),
+            _ => None,
+        }
+    }
+
+    pub fn bytes(&self) -> Option<&[u8]> {
+        match &self.data {
+            OutputData::Bytes(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    pub fn into_file(self) -> Option<OutputPath> {
+        match self.data {
+            OutputData::File(path) => Some(path),
+            _ => None,
+        }
+    }
+
+    /// Keeps the output file, see `OutputPath::keep`.
+    pub fn keep(self) -> Option<PathBuf> {
+        self.into_file().map(OutputPath::keep)
+    }
+
+    /// Moves the output file to `to`, see `OutputPath::persist`.
+    pub fn persist(mut self, to: impl AsRef<Path>) -> Result<PathBuf, PersistError<RenderOutput>> {
+        match std::mem::replace(&mut self.data, OutputData::Written) {
+            OutputData::File(file) => file.persist(to).map_err(|err| {
+                self.data = OutputData::File(*err.output);
+                PersistError {
+                    error: err.error,
+                    output: Box::new(self),
+                }
+            }),
+            data => {
+                self.data = data;
+                Err(PersistError {
+                    error: WkhtmlError::io(
+                        "Failed to persist output",
+                        std::io::Error::other("the render has no output file"),
+                    ),
+                    output: Box::new(self),
+                })
+            }
+        }
+    }
+
+    pub fn into_bytes(self) -> Option<Vec<u8>> {
+        match self.data {
+            OutputData::Bytes(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    /// Fills `size` and `page_count` from the data.
+    pub(crate) fn measure(&mut self) {
+        let (size, page_count) = match &self.data {
+            OutputData::File(path) => Self::measure_file(path),
+            OutputData::Path(path) => Self::measure_file(path),
+            OutputData::Bytes(bytes) => (
+                bytes.len() as u64,
+                bytes.starts_with(PDF_MAGIC).then(|| count_pdf_pages(bytes)),
+            ),
+            OutputData::Written => return,
+        };
+        self.size = size;
+        self.page_count = page_count;
+    }
+
+    fn measure_file(path: &Path) -> (u64, Option<u32>) {
+        let size = fs::metadata(path).map(|meta| meta.len()).unwrap_or(0);
+        let page_count = Self::is_pdf_file(path)
+            .then(|| fs::read(path).ok())
+            .flatten()
+            .map(|bytes| count_pdf_pages(&bytes));
+        (size, page_count)
+    }
+
+    fn is_pdf_file(path: &Path) -> bool {
+        let mut magic = [0; PDF_MAGIC.len()];
+        fs::File::open(path)
+            .and_then(|mut file| file.read_exact(&mut magic))
+            .is_ok()
+            && magic == PDF_MAGIC
+    }
+}
+
+const PDF_MAGIC: &[u8] = b"%PDF-";
+
+/// Counts the `/Type /Page` objects of a PDF, skipping the `/Type /Pages` tree nodes.
+fn count_pdf_pages(pdf: &[u8]) -> u32 {
+    let mut count = 0;
+    let mut rest = pdf;
+    while let Some(start) = find(rest, b"/Type") {
+        rest = &rest[start + b"/Type".len()..];
+        let skipped = rest.iter().take_while(|b| b.is_ascii_whitespace()).count();
+        rest = &rest[skipped..];
+        if let Some(after) = rest.strip_prefix(b"/Page") {
+            if !after.first().is_some_and(|b| b.is_ascii_alphanumeric()) {
+                count += 1;
+            }
+        }
+    }
+    count
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
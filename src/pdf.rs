@@ -1,14 +1,26 @@
 use crate::app::WkhtmlError;
 use crate::app::WkhtmlInput;
-use crate::core::Core;
-use std::collections::{HashMap, HashSet};
-use std::path::PathBuf;
+use crate::batch::{run_batch, BatchConfig, BatchItem, BatchReport};
+use crate::core::{Core, RawArg};
+use crate::document::{PdfDocument, PdfObject};
+use crate::options::check_values;
+use crate::output::RenderOutput;
+use crate::pdf_options::{option_section, PdfOptions};
+use crate::pool::RenderPool;
+use crate::progress::{Progress, ProgressHandler};
+use crate::renderer::Renderer;
+use crate::sandbox::SandboxPolicy;
+use crate::url_policy::UrlPolicy;
+use std::collections::HashMap;
 use std::env;
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
 
 #[derive(Debug, Clone)]
-pub struct PdfApp {
-    pub app: Core,
-    pub options: HashMap<String, String>,
+pub struct PdfApp<R = Core> {
+    pub app: R,
+    pub options: PdfOptions,
 }
 
 impl PdfApp {
@@ -18,7 +30,7 @@ impl PdfApp {
 
         Ok(Self {
             app: Core::new(wkhtmltopdf_cmd)?,
-            options: HashMap::new(),
+            options: PdfOptions::default(),
         })
     }
 
@@ -27,6 +39,64 @@ impl PdfApp {
         Ok(self)
     }
 
+    pub fn set_timeout(&mut self, timeout: Duration) -> Result<&mut Self, WkhtmlError> {
+        self.app.set_timeout(Some(timeout));
+        Ok(self)
+    }
+
+    /// Shares the slots of `pool` with the other apps using it, see `RenderPool`.
+    pub fn set_pool(&mut self, pool: &RenderPool) -> Result<&mut Self, WkhtmlError> {
+        self.app.set_pool(Some(pool.clone()));
+        Ok(self)
+    }
+
+    /// Passes the arguments on the tool's stdin instead of its command line, so
+    /// secrets can't be read from the process list, see `Core::set_secure_args`.
+    pub fn set_secure_args(&mut self, secure_args: bool) -> Result<&mut Self, WkhtmlError> {
+        self.app.set_secure_args(secure_args);
+        Ok(self)
+    }
+
+    /// Renders untrusted content within `sandbox`, see `SandboxPolicy`.
+    pub fn set_sandbox(&mut self, sandbox: SandboxPolicy) -> Result<&mut Self, WkhtmlError> {
+        self.app.set_sandbox(Some(sandbox));
+        Ok(self)
+    }
+
+    /// Refuses URL inputs that `url_policy` does not allow, see `UrlPolicy`.
+    pub fn set_url_policy(&mut self, url_policy: UrlPolicy) -> Result<&mut Self, WkhtmlError> {
+        self.app.set_url_policy(Some(url_policy));
+        Ok(self)
+    }
+
+    /// Calls `callback` with the phase and percentage of every render as the
+    /// tool reports them.
+    pub fn on_progress(
+        &mut self,
+        callback: impl Fn(&Progress) + Send + Sync + 'static,
+    ) -> Result<&mut Self, WkhtmlError> {
+        self.app.set_progress(Some(ProgressHandler::new(callback)));
+        Ok(self)
+    }
+
+    /// Returns a copy of this app whose renders are killed after `timeout`,
+    /// for a single call: `app.with_timeout(timeout).run(input, name)`.
+    pub fn with_timeout(&self, timeout: Duration) -> Self {
+        let mut app = self.clone();
+        app.app.set_timeout(Some(timeout));
+        app
+    }
+}
+
+impl<R: Renderer> PdfApp<R> {
+    /// Builds an app on top of another backend, such as a `MockRenderer`.
+    pub fn with_renderer(renderer: R) -> Self {
+        Self {
+            app: renderer,
+            options: PdfOptions::default(),
+        }
+    }
+
     pub fn set_args(&mut self, args: HashMap<&str, &str>) -> Result<&mut Self, WkhtmlError> {
         for (key, value) in args {
             self.set_arg(key, value)?;
@@ -35,164 +105,129 @@ impl PdfApp {
     }
 
     pub fn set_arg(&mut self, key: &str, arg: &str) -> Result<&mut Self, WkhtmlError> {
-        if Self::validate_option(key) {
-            self.options.insert(key.into(), arg.into());
-            Ok(self)
-        } else {
-            Err(WkhtmlError::ServiceErr(format!("Invalid option: {}", key)))
+        Self::check_option(key, &[arg])?;
+        self.options.extra.retain(|option| option.key != key);
+        self.options.extra.push(RawArg::new(key, &[arg]));
+        Ok(self)
+    }
+
+    pub fn add_arg(&mut self, key: &str, values: &[&str]) -> Result<&mut Self, WkhtmlError> {
+        Self::check_option(key, values)?;
+        self.options.extra.push(RawArg::new(key, values));
+        Ok(self)
+    }
+
+    /// Like `add_arg`, for values redacted from logs and `RenderOutput::command_line`.
+    pub fn add_secret_arg(&mut self, key: &str, values: &[&str]) -> Result<&mut Self, WkhtmlError> {
+        Self::check_option(key, values)?;
+        self.options.extra.push(RawArg::secret(key, values));
+        Ok(self)
+    }
+
+    pub fn set_options(&mut self, options: PdfOptions) -> Result<&mut Self, WkhtmlError> {
+        Self::check_options(&options)?;
+        self.options = options;
+        Ok(self)
+    }
+
+    fn check_options(options: &PdfOptions) -> Result<(), WkhtmlError> {
+        for option in &options.extra {
+            let values: Vec<&str> = option.values.iter().map(String::as_str).collect();
+            Self::check_option(&option.key, &values)?;
+        }
+        Ok(())
+    }
+
+    fn check_option(key: &str, values: &[&str]) -> Result<(), WkhtmlError> {
+        if !Self::validate_option(key) {
+            return Err(WkhtmlError::invalid_option(key, "unknown option"));
+        }
+        if key == "cover" && !matches!(values, [cover] if *cover != "true") {
+            return Err(WkhtmlError::invalid_option(
+                key,
+                "needs the file or URL of the cover page",
+            ));
         }
+        check_values(key, values)
     }
 
-    pub fn run(&self, input: WkhtmlInput, name: &str) -> Result<PathBuf, WkhtmlError> {
+    pub fn run(&self, input: WkhtmlInput, name: &str) -> Result<RenderOutput, WkhtmlError> {
+        let name = format!("{}.pdf", name);
+        let request = self.options.to_request(input);
+        self.app.render_to_file(request, &name)
+    }
+
+    /// Renders straight to `path`, replacing it atomically once the render succeeded.
+    pub fn run_to_path(
+        &self,
+        input: WkhtmlInput,
+        path: impl AsRef<Path>,
+    ) -> Result<RenderOutput, WkhtmlError> {
+        let request = self.options.to_request(input);
+        self.app.render_to_path(request, path.as_ref())
+    }
+
+    /// Renders every item, `config.parallelism` at a time, returning one result
+    /// per item in input order.
+    pub fn render_batch<'a>(
+        &self,
+        items: impl IntoIterator<Item = BatchItem<'a, PdfOptions>>,
+        config: &BatchConfig,
+    ) -> BatchReport
+    where
+        R: Sync,
+    {
+        run_batch(items, config, |item| {
+            let options = match &item.options {
+                Some(options) => {
+                    Self::check_options(options)?;
+                    options
+                }
+                None => &self.options,
+            };
+            let name = format!("{}.pdf", item.name);
+            let request = options.to_request(item.input.clone());
+            self.app.render_to_file(request, &name)
+        })
+    }
+
+    pub fn render_to_vec(&self, input: WkhtmlInput) -> Result<Vec<u8>, WkhtmlError> {
+        let request = self.options.to_request(input);
+        let output = self.app.render_to_bytes(request)?;
+        Ok(output.into_bytes().unwrap_or_default())
+    }
+
+    pub fn run_to_writer<W: Write>(
+        &self,
+        input: WkhtmlInput,
+        writer: &mut W,
+    ) -> Result<u64, WkhtmlError> {
+        let request = self.options.to_request(input);
+        Ok(self.app.render_to_writer(request, writer)?.size)
+    }
+
+    pub fn run_document(
+        &self,
+        document: &PdfDocument,
+        name: &str,
+    ) -> Result<RenderOutput, WkhtmlError> {
+        if !document
+            .objects
+            .iter()
+            .any(|object| matches!(object, PdfObject::Page(..)))
+        {
+            return Err(WkhtmlError::InvalidDocument(
+                "a document needs at least one page".to_string(),
+            ));
+        }
         let name = format!("{}.pdf", name);
-        let args = Core::build_args(&self.options);
-        self.app.run(input, &name, args)
+        let request = document
+            .to_request(self.options.global_args())
+            .with_secrets(self.options.secrets());
+        self.app.render_to_file(request, &name)
     }
 
     fn validate_option(key: &str) -> bool {
-        static OPTIONS: std::sync::LazyLock<HashSet<&'static str>> = std::sync::LazyLock::new(|| {
-            HashSet::from([
-                // Global options
-                "collate",
-                "no-collate",
-                "cookie-jar",
-                "copies",
-                "dpi",
-                "extended-help",
-                "grayscale",
-                "help",
-                "htmldoc",
-                "ignore-load-errors",
-                "image-dpi",
-                "image-quality",
-                "license",
-                "log-level",
-                "lowquality",
-                "manpage",
-                "margin-bottom",
-                "margin-left",
-                "margin-right",
-                "margin-top",
-                "orientation",
-                "page-height",
-                "page-size",
-                "page-width",
-                "no-pdf-compression",
-                "quiet",
-                "read-args-from-stdin",
-                "readme",
-                "title",
-                "use-xserver",
-                "version",
-                // Outline options
-                "dump-default-toc-xsl",
-                "dump-outline",
-                "outline",
-                "no-outline",
-                "outline-depth",
-                "output-format",
-                // Page options
-                "allow",
-                "background",
-                "no-background",
-                "bypass-proxy-for",
-                "cache-dir",
-                "checkbox-checked-svg",
-                "checkbox-svg",
-                "cookie",
-                "custom-header",
-                "custom-header-propagation",
-                "no-custom-header-propagation",
-                "debug-javascript",
-                "no-debug-javascript",
-                "default-header",
-                "encoding",
-                "disable-external-links",
-                "enable-external-links",
-                "disable-forms",
-                "enable-forms",
-                "images",
-                "no-images",
-                "disable-internal-links",
-                "enable-internal-links",
-                "disable-javascript",
-                "enable-javascript",
-                "javascript-delay",
-                "keep-relative-links",
-                "load-error-handling",
-                "load-media-error-handling",
-                "disable-local-file-access",
-                "enable-local-file-access",
-                "minimum-font-size",
-                "exclude-from-outline",
-                "include-in-outline",
-                "page-offset",
-                "password",
-                "disable-plugins",
-                "enable-plugins",
-                "post",
-                "post-file",
-                "print-media-type",
-                "no-print-media-type",
-                "proxy",
-                "proxy-hostname-lookup",
-                "radiobutton-checked-svg",
-                "radiobutton-svg",
-                "redirect-delay",
-                "resolve-relative-links",
-                "run-script",
-                "disable-smart-shrinking",
-                "enable-smart-shrinking",
-                "ssl-crt-path",
-                "ssl-key-password",
-                "ssl-key-path",
-                "stop-slow-scripts",
-                "no-stop-slow-scripts",
-                "disable-toc-back-links",
-                "enable-toc-back-links",
-                "user-style-sheet",
-                "username",
-                "viewport-size",
-                "window-status",
-                "zoom",
-                // Headers and footer options
-                "footer-center",
-                "footer-font-name",
-                "footer-font-size",
-                "footer-html",
-                "footer-left",
-                "footer-line",
-                "no-footer-line",
-                "footer-right",
-                "footer-spacing",
-                "header-center",
-                "header-font-name",
-                "header-font-size",
-                "header-html",
-                "header-left",
-                "header-line",
-                "no-header-line",
-                "header-right",
-                "header-spacing",
-                "replace",
-                // Cover object
-                "cover",
-                // TOC object
-                "toc",
-                // TOC options
-                "disable-dotted-lines",
-                "toc-depth",
-                "toc-font-name",
-                "toc-l1-font-size",
-                "toc-header-text",
-                "toc-header-font-name",
-                "toc-header-font-size",
-                "toc-level-indentation",
-                "disable-toc-links",
-                "toc-text-size-shrink",
-                "xsl-style-sheet",
-            ])
-        });
-        OPTIONS.contains(key)
+        option_section(key).is_some()
     }
 }
@@ -0,0 +1,652 @@
+use crate::app::WkhtmlInput;
+use crate::core::{Core, RawArg};
+use crate::options::{
+    push_flag, push_pairs, push_repeated, push_toggle, push_value, Length, LoadErrorHandling,
+    LogLevel, Orientation, OutputFormat, PageSize,
+};
+use crate::renderer::{RenderObject, RenderRequest};
+use std::collections::HashMap;
+use std::sync::LazyLock;
+
+/// Typed wkhtmltopdf options.
+///
+/// Informational switches such as `help`, `version` or `readme` are not modelled
+/// here, they can still be passed through `PdfApp::set_arg`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PdfOptions {
+    // Global options
+    pub collate: Option<bool>,
+    pub cookie_jar: Option<String>,
+    pub copies: Option<u32>,
+    pub dpi: Option<u32>,
+    pub grayscale: bool,
+    pub ignore_load_errors: bool,
+    pub image_dpi: Option<u32>,
+    pub image_quality: Option<u8>,
+    pub log_level: Option<LogLevel>,
+    pub lowquality: bool,
+    pub margin_bottom: Option<Length>,
+    pub margin_left: Option<Length>,
+    pub margin_right: Option<Length>,
+    pub margin_top: Option<Length>,
+    pub orientation: Option<Orientation>,
+    pub page_height: Option<Length>,
+    pub page_size: Option<PageSize>,
+    pub page_width: Option<Length>,
+    pub no_pdf_compression: bool,
+    pub quiet: bool,
+    pub title: Option<String>,
+    pub use_xserver: bool,
+    // Outline options
+    pub dump_outline: Option<String>,
+    pub outline: Option<bool>,
+    pub outline_depth: Option<u32>,
+    pub output_format: Option<OutputFormat>,
+    pub page: PageOptions,
+    pub header_footer: HeaderFooterOptions,
+    // Cover object
+    pub cover: Option<String>,
+    // TOC object
+    pub toc: Option<TocOptions>,
+    /// Raw options set through `PdfApp::set_arg` and `PdfApp::add_arg`.
+    pub extra: Vec<RawArg>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PageOptions {
+    pub allow: Vec<String>,
+    pub background: Option<bool>,
+    pub bypass_proxy_for: Vec<String>,
+    pub cache_dir: Option<String>,
+    pub checkbox_checked_svg: Option<String>,
+    pub checkbox_svg: Option<String>,
+    pub cookies: Vec<(String, String)>,
+    pub custom_headers: Vec<(String, String)>,
+    pub custom_header_propagation: Option<bool>,
+    pub debug_javascript: Option<bool>,
+    pub default_header: bool,
+    pub encoding: Option<String>,
+    pub external_links: Option<bool>,
+    pub forms: Option<bool>,
+    pub images: Option<bool>,
+    pub internal_links: Option<bool>,
+    pub javascript: Option<bool>,
+    pub javascript_delay: Option<u32>,
+    pub keep_relative_links: bool,
+    pub load_error_handling: Option<LoadErrorHandling>,
+    pub load_media_error_handling: Option<LoadErrorHandling>,
+    pub local_file_access: Option<bool>,
+    pub minimum_font_size: Option<u32>,
+    pub include_in_outline: Option<bool>,
+    pub page_offset: Option<i32>,
+    pub password: Option<String>,
+    pub plugins: Option<bool>,
+    pub post: Vec<(String, String)>,
+    pub post_files: Vec<(String, String)>,
+    pub print_media_type: Option<bool>,
+    pub proxy: Option<String>,
+    pub proxy_hostname_lookup: bool,
+    pub radiobutton_checked_svg: Option<String>,
+    pub radiobutton_svg: Option<String>,
+    pub redirect_delay: Option<u32>,
+    pub resolve_relative_links: bool,
+    pub run_script: Vec<String>,
+    pub smart_shrinking: Option<bool>,
+    pub ssl_crt_path: Option<String>,
+    pub ssl_key_password: Option<String>,
+    pub ssl_key_path: Option<String>,
+    pub stop_slow_scripts: Option<bool>,
+    pub toc_back_links: Option<bool>,
+    pub user_style_sheet: Option<String>,
+    pub username: Option<String>,
+    pub viewport_size: Option<String>,
+    pub window_status: Option<String>,
+    pub zoom: Option<f32>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HeaderFooterOptions {
+    pub footer_center: Option<String>,
+    pub footer_font_name: Option<String>,
+    pub footer_font_size: Option<u32>,
+    pub footer_html: Option<String>,
+    pub footer_left: Option<String>,
+    pub footer_line: Option<bool>,
+    pub footer_right: Option<String>,
+    pub footer_spacing: Option<f32>,
+    pub header_center: Option<String>,
+    pub header_font_name: Option<String>,
+    pub header_font_size: Option<u32>,
+    pub header_html: Option<String>,
+    pub header_left: Option<String>,
+    pub header_line: Option<bool>,
+    pub header_right: Option<String>,
+    pub header_spacing: Option<f32>,
+    pub replace: Vec<(String, String)>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TocOptions {
+    pub disable_dotted_lines: bool,
+    pub depth: Option<u32>,
+    pub font_name: Option<String>,
+    pub l1_font_size: Option<u32>,
+    pub header_text: Option<String>,
+    pub header_font_name: Option<String>,
+    pub header_font_size: Option<u32>,
+    pub level_indentation: Option<String>,
+    pub disable_links: bool,
+    pub text_size_shrink: Option<f32>,
+    pub xsl_style_sheet: Option<String>,
+}
+
+impl PdfOptions {
+    /// Arguments that come before the document objects: global, outline and
+    /// default page options. The `cover` and `toc` objects are left out.
+    pub fn global_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        // Global options
+        push_toggle(&mut args, "collate", "no-collate", self.collate);
+        push_value(&mut args, "cookie-jar", &self.cookie_jar);
+        push_value(&mut args, "copies", &self.copies);
+        push_value(&mut args, "dpi", &self.dpi);
+        push_flag(&mut args, "grayscale", self.grayscale);
+        push_flag(&mut args, "ignore-load-errors", self.ignore_load_errors);
+        push_value(&mut args, "image-dpi", &self.image_dpi);
+        push_value(&mut args, "image-quality", &self.image_quality);
+        push_value(&mut args, "log-level", &self.log_level);
+        push_flag(&mut args, "lowquality", self.lowquality);
+        push_value(&mut args, "margin-bottom", &self.margin_bottom);
+        push_value(&mut args, "margin-left", &self.margin_left);
+        push_value(&mut args, "margin-right", &self.margin_right);
+        push_value(&mut args, "margin-top", &self.margin_top);
+        push_value(&mut args, "orientation", &self.orientation);
+        push_value(&mut args, "page-height", &self.page_height);
+        push_value(&mut args, "page-size", &self.page_size);
+        push_value(&mut args, "page-width", &self.page_width);
+        push_flag(&mut args, "no-pdf-compression", self.no_pdf_compression);
+        push_flag(&mut args, "quiet", self.quiet);
+        push_value(&mut args, "title", &self.title);
+        push_flag(&mut args, "use-xserver", self.use_xserver);
+        // Outline options
+        push_value(&mut args, "dump-outline", &self.dump_outline);
+        push_toggle(&mut args, "outline", "no-outline", self.outline);
+        push_value(&mut args, "outline-depth", &self.outline_depth);
+        push_value(&mut args, "output-format", &self.output_format);
+        args.extend(self.raw_args(&[OptionSection::Global, OptionSection::Outline]));
+        // Page options given before any object apply to every page
+        args.extend(self.page.to_args());
+        args.extend(self.raw_args(&[OptionSection::Page]));
+        args.extend(self.header_footer.to_args());
+        args.extend(self.raw_args(&[OptionSection::HeaderFooter]));
+        args
+    }
+
+    /// The arguments of `to_request`, without the input.
+    pub fn to_args(&self) -> Vec<String> {
+        let (mut args, objects) = self.request_parts();
+        for object in &objects {
+            args.extend(object.keyword.map(str::to_string));
+            if let Some(input) = &object.input {
+                Core::push_input(&mut args, input);
+            }
+            args.extend(object.args.iter().cloned());
+        }
+        args
+    }
+
+    /// The request rendering `input` with these options. The cover and the table
+    /// of contents are objects of their own, so the cover is checked like any
+    /// other input and options added before the objects apply to every page.
+    pub fn to_request<'a>(&'a self, input: WkhtmlInput<'a>) -> RenderRequest<'a> {
+        let (args, mut objects) = self.request_parts();
+        objects.push(RenderObject {
+            keyword: None,
+            input: Some(input),
+            args: Vec::new(),
+        });
+        RenderRequest {
+            args,
+            objects,
+            secrets: self.secrets(),
+        }
+    }
+
+    /// The options placed before the objects, then the cover and TOC objects.
+    fn request_parts(&self) -> (Vec<String>, Vec<RenderObject<'_>>) {
+        let mut args = self.global_args();
+        let raw_covers = self
+            .extra
+            .iter()
+            .filter(|option| option.key == "cover")
+            .filter_map(|option| match option.values.as_slice() {
+                [cover] if cover != "false" => Some(cover.as_str()),
+                _ => None,
+            });
+        let mut objects: Vec<RenderObject> = self
+            .cover
+            .as_deref()
+            .into_iter()
+            .chain(raw_covers)
+            .map(|cover| RenderObject {
+                keyword: Some("cover"),
+                input: Some(cover_input(cover)),
+                args: Vec::new(),
+            })
+            .collect();
+        let raw_toc = self.extra.iter().any(|option| {
+            option.key == "toc" && !matches!(option.values.as_slice(), [v] if v == "false")
+        });
+        let toc_args = self.raw_args(&[OptionSection::TocOption]);
+        // A raw `toc` next to the typed one would add a second table of contents
+        match &self.toc {
+            Some(toc) => objects.push(RenderObject {
+                keyword: Some("toc"),
+                input: None,
+                args: toc.to_args().into_iter().chain(toc_args).collect(),
+            }),
+            None if raw_toc => objects.push(RenderObject {
+                keyword: Some("toc"),
+                input: None,
+                args: toc_args,
+            }),
+            None => args.extend(toc_args),
+        }
+        (args, objects)
+    }
+
+    /// Values of the options that must stay out of logs: passwords, cookie and
+    /// header values, and raw options marked secret.
+    pub fn secrets(&self) -> Vec<String> {
+        let mut secrets = self.page.secrets();
+        secrets.extend(
+            self.extra
+                .iter()
+                .flat_map(|option| option.secret_values())
+                .cloned(),
+        );
+        secrets
+    }
+
+    /// Raw options of the given sections, in canonical order.
+    fn raw_args(&self, sections: &[OptionSection]) -> Vec<String> {
+        let options: Vec<RawArg> = self
+            .extra
+            .iter()
+            .filter(|option| {
+                let section = option_section(&option.key).unwrap_or(OptionSection::Global);
+                sections.contains(&section)
+            })
+            .cloned()
+            .collect();
+        Core::build_args(&options)
+    }
+}
+
+/// A cover is a URL when it has a scheme, a file otherwise.
+fn cover_input(cover: &str) -> WkhtmlInput<'_> {
+    if cover.contains("://") {
+        WkhtmlInput::Url(cover)
+    } else {
+        WkhtmlInput::File(cover)
+    }
+}
+
+impl PageOptions {
+    pub fn secrets(&self) -> Vec<String> {
+        let pairs = self.cookies.iter().chain(&self.custom_headers);
+        let mut secrets: Vec<String> = pairs.map(|(_, value)| value.clone()).collect();
+        secrets.extend(self.password.iter().cloned());
+        secrets.extend(self.ssl_key_password.iter().cloned());
+        secrets
+    }
+
+    pub fn to_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        push_repeated(&mut args, "allow", &self.allow);
+        push_toggle(&mut args, "background", "no-background", self.background);
+        push_repeated(&mut args, "bypass-proxy-for", &self.bypass_proxy_for);
+        push_value(&mut args, "cache-dir", &self.cache_dir);
+        push_value(
+            &mut args,
+            "checkbox-checked-svg",
+            &self.checkbox_checked_svg,
+        );
+        push_value(&mut args, "checkbox-svg", &self.checkbox_svg);
+        push_pairs(&mut args, "cookie", &self.cookies);
+        push_pairs(&mut args, "custom-header", &self.custom_headers);
+        push_toggle(
+            &mut args,
+            "custom-header-propagation",
+            "no-custom-header-propagation",
+            self.custom_header_propagation,
+        );
+        push_toggle(
+            &mut args,
+            "debug-javascript",
+            "no-debug-javascript",
+            self.debug_javascript,
+        );
+        push_flag(&mut args, "default-header", self.default_header);
+        push_value(&mut args, "encoding", &self.encoding);
+        push_toggle(
+            &mut args,
+            "enable-external-links",
+            "disable-external-links",
+            self.external_links,
+        );
+        push_toggle(&mut args, "enable-forms", "disable-forms", self.forms);
+        push_toggle(&mut args, "images", "no-images", self.images);
+        push_toggle(
+            &mut args,
+            "enable-internal-links",
+            "disable-internal-links",
+            self.internal_links,
+        );
+        push_toggle(
+            &mut args,
+            "enable-javascript",
+            "disable-javascript",
+            self.javascript,
+        );
+        push_value(&mut args, "javascript-delay", &self.javascript_delay);
+        push_flag(&mut args, "keep-relative-links", self.keep_relative_links);
+        push_value(&mut args, "load-error-handling", &self.load_error_handling);
+        push_value(
+            &mut args,
+            "load-media-error-handling",
+            &self.load_media_error_handling,
+        );
+        push_toggle(
+            &mut args,
+            "enable-local-file-access",
+            "disable-local-file-access",
+            self.local_file_access,
+        );
+        push_value(&mut args, "minimum-font-size", &self.minimum_font_size);
+        push_toggle(
+            &mut args,
+            "include-in-outline",
+            "exclude-from-outline",
+            self.include_in_outline,
+        );
+        push_value(&mut args, "page-offset", &self.page_offset);
+        push_value(&mut args, "password", &self.password);
+        push_toggle(&mut args, "enable-plugins", "disable-plugins", self.plugins);
+        push_pairs(&mut args, "post", &self.post);
+        push_pairs(&mut args, "post-file", &self.post_files);
+        push_toggle(
+            &mut args,
+            "print-media-type",
+            "no-print-media-type",
+            self.print_media_type,
+        );
+        push_value(&mut args, "proxy", &self.proxy);
+        push_flag(
+            &mut args,
+            "proxy-hostname-lookup",
+            self.proxy_hostname_lookup,
+        );
+        push_value(
+            &mut args,
+            "radiobutton-checked-svg",
+            &self.radiobutton_checked_svg,
+        );
+        push_value(&mut args, "radiobutton-svg", &self.radiobutton_svg);
+        push_value(&mut args, "redirect-delay", &self.redirect_delay);
+        push_flag(
+            &mut args,
+            "resolve-relative-links",
+            self.resolve_relative_links,
+        );
+        push_repeated(&mut args, "run-script", &self.run_script);
+        push_toggle(
+            &mut args,
+            "enable-smart-shrinking",
+            "disable-smart-shrinking",
+            self.smart_shrinking,
+        );
+        push_value(&mut args, "ssl-crt-path", &self.ssl_crt_path);
+        push_value(&mut args, "ssl-key-password", &self.ssl_key_password);
+        push_value(&mut args, "ssl-key-path", &self.ssl_key_path);
+        push_toggle(
+            &mut args,
+            "stop-slow-scripts",
+            "no-stop-slow-scripts",
+            self.stop_slow_scripts,
+        );
+        push_toggle(
+            &mut args,
+            "enable-toc-back-links",
+            "disable-toc-back-links",
+            self.toc_back_links,
+        );
+        push_value(&mut args, "user-style-sheet", &self.user_style_sheet);
+        push_value(&mut args, "username", &self.username);
+        push_value(&mut args, "viewport-size", &self.viewport_size);
+        push_value(&mut args, "window-status", &self.window_status);
+        push_value(&mut args, "zoom", &self.zoom);
+        args
+    }
+}
+
+impl HeaderFooterOptions {
+    pub fn to_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        push_value(&mut args, "footer-center", &self.footer_center);
+        push_value(&mut args, "footer-font-name", &self.footer_font_name);
+        push_value(&mut args, "footer-font-size", &self.footer_font_size);
+        push_value(&mut args, "footer-html", &self.footer_html);
+        push_value(&mut args, "footer-left", &self.footer_left);
+        push_toggle(&mut args, "footer-line", "no-footer-line", self.footer_line);
+        push_value(&mut args, "footer-right", &self.footer_right);
+        push_value(&mut args, "footer-spacing", &self.footer_spacing);
+        push_value(&mut args, "header-center", &self.header_center);
+        push_value(&mut args, "header-font-name", &self.header_font_name);
+        push_value(&mut args, "header-font-size", &self.header_font_size);
+        push_value(&mut args, "header-html", &self.header_html);
+        push_value(&mut args, "header-left", &self.header_left);
+        push_toggle(&mut args, "header-line", "no-header-line", self.header_line);
+        push_value(&mut args, "header-right", &self.header_right);
+        push_value(&mut args, "header-spacing", &self.header_spacing);
+        push_pairs(&mut args, "replace", &self.replace);
+        args
+    }
+}
+
+impl TocOptions {
+    pub fn to_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        push_flag(&mut args, "disable-dotted-lines", self.disable_dotted_lines);
+        push_value(&mut args, "toc-depth", &self.depth);
+        push_value(&mut args, "toc-font-name", &self.font_name);
+        push_value(&mut args, "toc-l1-font-size", &self.l1_font_size);
+        push_value(&mut args, "toc-header-text", &self.header_text);
+        push_value(&mut args, "toc-header-font-name", &self.header_font_name);
+        push_value(&mut args, "toc-header-font-size", &self.header_font_size);
+        push_value(&mut args, "toc-level-indentation", &self.level_indentation);
+        push_flag(&mut args, "disable-toc-links", self.disable_links);
+        push_value(&mut args, "toc-text-size-shrink", &self.text_size_shrink);
+        push_value(&mut args, "xsl-style-sheet", &self.xsl_style_sheet);
+        args
+    }
+}
+
+/// Where an option belongs on the wkhtmltopdf command line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum OptionSection {
+    Global,
+    Outline,
+    Page,
+    HeaderFooter,
+    Cover,
+    Toc,
+    TocOption,
+}
+
+pub(crate) fn option_section(key: &str) -> Option<OptionSection> {
+    static SECTIONS: LazyLock<HashMap<&'static str, OptionSection>> = LazyLock::new(|| {
+        let mut sections = HashMap::new();
+        // Global options
+        for key in [
+            "collate",
+            "no-collate",
+            "cookie-jar",
+            "copies",
+            "dpi",
+            "extended-help",
+            "grayscale",
+            "help",
+            "htmldoc",
+            "ignore-load-errors",
+            "image-dpi",
+            "image-quality",
+            "license",
+            "log-level",
+            "lowquality",
+            "manpage",
+            "margin-bottom",
+            "margin-left",
+            "margin-right",
+            "margin-top",
+            "orientation",
+            "page-height",
+            "page-size",
+            "page-width",
+            "no-pdf-compression",
+            "quiet",
+            "read-args-from-stdin",
+            "readme",
+            "title",
+            "use-xserver",
+            "version",
+        ] {
+            sections.insert(key, OptionSection::Global);
+        }
+        // Outline options
+        for key in [
+            "dump-default-toc-xsl",
+            "dump-outline",
+            "outline",
+            "no-outline",
+            "outline-depth",
+            "output-format",
+        ] {
+            sections.insert(key, OptionSection::Outline);
+        }
+        // Page options
+        for key in [
+            "allow",
+            "background",
+            "no-background",
+            "bypass-proxy-for",
+            "cache-dir",
+            "checkbox-checked-svg",
+            "checkbox-svg",
+            "cookie",
+            "custom-header",
+            "custom-header-propagation",
+            "no-custom-header-propagation",
+            "debug-javascript",
+            "no-debug-javascript",
+            "default-header",
+            "encoding",
+            "disable-external-links",
+            "enable-external-links",
+            "disable-forms",
+            "enable-forms",
+            "images",
+            "no-images",
+            "disable-internal-links",
+            "enable-internal-links",
+            "disable-javascript",
+            "enable-javascript",
+            "javascript-delay",
+            "keep-relative-links",
+            "load-error-handling",
+            "load-media-error-handling",
+            "disable-local-file-access",
+            "enable-local-file-access",
+            "minimum-font-size",
+            "exclude-from-outline",
+            "include-in-outline",
+            "page-offset",
+            "password",
+            "disable-plugins",
+            "enable-plugins",
+            "post",
+            "post-file",
+            "print-media-type",
+            "no-print-media-type",
+            "proxy",
+            "proxy-hostname-lookup",
+            "radiobutton-checked-svg",
+            "radiobutton-svg",
+            "redirect-delay",
+            "resolve-relative-links",
+            "run-script",
+            "disable-smart-shrinking",
+            "enable-smart-shrinking",
+            "ssl-crt-path",
+            "ssl-key-password",
+            "ssl-key-path",
+            "stop-slow-scripts",
+            "no-stop-slow-scripts",
+            "disable-toc-back-links",
+            "enable-toc-back-links",
+            "user-style-sheet",
+            "username",
+            "viewport-size",
+            "window-status",
+            "zoom",
+        ] {
+            sections.insert(key, OptionSection::Page);
+        }
+        // Headers and footer options
+        for key in [
+            "footer-center",
+            "footer-font-name",
+            "footer-font-size",
+            "footer-html",
+            "footer-left",
+            "footer-line",
+            "no-footer-line",
+            "footer-right",
+            "footer-spacing",
+            "header-center",
+            "header-font-name",
+            "header-font-size",
+            "header-html",
+            "header-left",
+            "header-line",
+            "no-header-line",
+            "header-right",
+            "header-spacing",
+            "replace",
+        ] {
+            sections.insert(key, OptionSection::HeaderFooter);
+        }
+        // Cover object
+        sections.insert("cover", OptionSection::Cover);
+        // TOC object
+        sections.insert("toc", OptionSection::Toc);
+        // TOC options
+        for key in [
+            "disable-dotted-lines",
+            "toc-depth",
+            "toc-font-name",
+            "toc-l1-font-size",
+            "toc-header-text",
+            "toc-header-font-name",
+            "toc-header-font-size",
+            "toc-level-indentation",
+            "disable-toc-links",
+            "toc-text-size-shrink",
+            "xsl-style-sheet",
+        ] {
+            sections.insert(key, OptionSection::TocOption);
+        }
+        sections
+    });
+    SECTIONS.get(key).copied()
+}
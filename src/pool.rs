@@ -0,0 +1,273 @@
+use crate::app::WkhtmlError;
+use log::warn;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// Limits of a `RenderPool`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolConfig {
+    /// Renders running at the same time, 0 is raised to 1.
+    pub max_concurrency: usize,
+    /// Renders allowed to wait for a slot, the next ones fail with `QueueFull`.
+    pub max_queue: usize,
+    /// How long a render waits for a slot before failing with `QueueTimeout`,
+    /// `None` waits forever.
+    pub queue_timeout: Option<Duration>,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            max_concurrency: 4,
+            max_queue: 64,
+            queue_timeout: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    pub queued: usize,
+    pub running: usize,
+    /// Renders that finished successfully.
+    pub completed: u64,
+    pub failed: u64,
+    /// Renders turned away because the queue was full.
+    pub rejected: u64,
+    /// Renders that gave up waiting for a slot.
+    pub timed_out: u64,
+}
+
+/// Bounds the number of wkhtmltopdf processes running at once.
+///
+/// Attach it to apps with `PdfApp::set_pool` / `ImgApp::set_pool`: their blocking
+/// and async renders then wait for a slot before spawning the tool. Clones share
+/// the same slots, so one pool can serve several apps.
+#[derive(Debug, Clone)]
+pub struct RenderPool {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    config: PoolConfig,
+    stats: Mutex<PoolStats>,
+    released: Condvar,
+    #[cfg(feature = "tokio")]
+    released_async: tokio::sync::Notify,
+}
+
+impl RenderPool {
+    pub fn new(mut config: PoolConfig) -> Self {
+        if config.max_concurrency == 0 {
+            // No render could ever start, every caller would wait forever
+            warn!("RenderPool max_concurrency of 0 raised to 1");
+            config.max_concurrency = 1;
+        }
+        Self {
+            inner: Arc::new(Inner {
+                config,
+                stats: Mutex::new(PoolStats::default()),
+                released: Condvar::new(),
+                #[cfg(feature = "tokio")]
+                released_async: tokio::sync::Notify::new(),
+            }),
+        }
+    }
+
+    pub fn config(&self) -> PoolConfig {
+        self.inner.config
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        *self.lock()
+    }
+
+    /// Waits for a free slot, blocking the current thread.
+    pub(crate) fn acquire(&self) -> Result<Permit, WkhtmlError> {
+        let started = Instant::now();
+        let mut stats = self.lock();
+        if !self.has_slot(&stats) {
+            let queued = self.enqueue(&mut stats)?;
+            loop {
+                stats = match self.remaining(started) {
+                    Some(remaining) if remaining.is_zero() => {
+                        return Err(self.queue_timeout(&mut stats, queued, started));
+                    }
+                    Some(remaining) => {
+                        let stats = self.inner.released.wait_timeout(stats, remaining);
+                        stats.unwrap_or_else(|e| e.into_inner()).0
+                    }
+                    None => {
+                        let stats = self.inner.released.wait(stats);
+                        stats.unwrap_or_else(|e| e.into_inner())
+                    }
+                };
+                if stats.running < self.inner.config.max_concurrency {
+                    queued.leave(&mut stats);
+                    break;
+                }
+            }
+        }
+        stats.running += 1;
+        Ok(Permit::new(self.clone()))
+    }
+
+    /// Waits for a free slot without blocking the runtime. Dropping the future
+    /// leaves the queue.
+    #[cfg(feature = "tokio")]
+    pub(crate) async fn acquire_async(&self) -> Result<Permit, WkhtmlError> {
+        let started = Instant::now();
+        let queued = {
+            let mut stats = self.lock();
+            if self.has_slot(&stats) {
+                stats.running += 1;
+                return Ok(Permit::new(self.clone()));
+            }
+            self.enqueue(&mut stats)?
+        };
+        let mut queued = QueuedGuard {
+            pool: self,
+            queued: Some(queued),
+        };
+        loop {
+            // Registered before checking, so a release in between is not missed
+            let notified = self.inner.released_async.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+            {
+                let mut stats = self.lock();
+                if stats.running < self.inner.config.max_concurrency {
+                    queued.leave(&mut stats);
+                    stats.running += 1;
+                    return Ok(Permit::new(self.clone()));
+                }
+            }
+            match self.remaining(started) {
+                Some(remaining) => {
+                    if tokio::time::timeout(remaining, notified).await.is_err() {
+                        let mut stats = self.lock();
+                        let queued = queued.queued.take().expect("still queued");
+                        return Err(self.queue_timeout(&mut stats, queued, started));
+                    }
+                }
+                None => notified.await,
+            }
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, PoolStats> {
+        self.inner.stats.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Whether a render can start right away, leaving the free slots the
+    /// queued renders are about to take.
+    fn has_slot(&self, stats: &PoolStats) -> bool {
+        stats.running + stats.queued < self.inner.config.max_concurrency
+    }
+
+    fn enqueue(&self, stats: &mut PoolStats) -> Result<Queued, WkhtmlError> {
+        if stats.queued >= self.inner.config.max_queue {
+            stats.rejected += 1;
+            warn!("Render queue is full, rejecting render");
+            return Err(WkhtmlError::QueueFull {
+                max_queue: self.inner.config.max_queue,
+            });
+        }
+        stats.queued += 1;
+        Ok(Queued)
+    }
+
+    fn remaining(&self, started: Instant) -> Option<Duration> {
+        let timeout = self.inner.config.queue_timeout?;
+        Some(timeout.saturating_sub(started.elapsed()))
+    }
+
+    fn queue_timeout(
+        &self,
+        stats: &mut PoolStats,
+        queued: Queued,
+        started: Instant,
+    ) -> WkhtmlError {
+        queued.leave(stats);
+        stats.timed_out += 1;
+        warn!(
+            "Render waited {:?} for a slot, giving up",
+            started.elapsed()
+        );
+        WkhtmlError::QueueTimeout {
+            waited: started.elapsed(),
+        }
+    }
+
+    fn release(&self, failed: bool) {
+        let mut stats = self.lock();
+        stats.running -= 1;
+        if failed {
+            stats.failed += 1;
+        } else {
+            stats.completed += 1;
+        }
+        drop(stats);
+        self.inner.released.notify_one();
+        #[cfg(feature = "tokio")]
+        self.inner.released_async.notify_waiters();
+    }
+}
+
+/// A place in the queue, given back with `leave`.
+struct Queued;
+
+impl Queued {
+    fn leave(self, stats: &mut PoolStats) {
+        stats.queued -= 1;
+    }
+}
+
+/// Leaves the queue when an async wait is cancelled.
+#[cfg(feature = "tokio")]
+struct QueuedGuard<'a> {
+    pool: &'a RenderPool,
+    queued: Option<Queued>,
+}
+
+#[cfg(feature = "tokio")]
+impl QueuedGuard<'_> {
+    fn leave(&mut self, stats: &mut PoolStats) {
+        if let Some(queued) = self.queued.take() {
+            queued.leave(stats);
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl Drop for QueuedGuard<'_> {
+    fn drop(&mut self) {
+        if let Some(queued) = self.queued.take() {
+            queued.leave(&mut self.pool.lock());
+        }
+    }
+}
+
+/// A running slot, given back to the pool when dropped.
+pub(crate) struct Permit {
+    pool: RenderPool,
+    failed: bool,
+}
+
+impl Permit {
+    fn new(pool: RenderPool) -> Self {
+        // Counted as failed unless `record` says otherwise, e.g. when cancelled
+        Self { pool, failed: true }
+    }
+
+    pub(crate) fn record<T>(mut self, result: &Result<T, WkhtmlError>) {
+        self.failed = result.is_err();
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.pool.release(self.failed);
+    }
+}
@@ -0,0 +1,126 @@
+use std::fmt;
+use std::io::{ErrorKind, Read};
+use std::sync::Arc;
+
+/// A progress update parsed from the tool's stderr, e.g. `Loading pages (1/6)`
+/// followed by `[=====>    ] 50%`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Progress {
+    pub phase: String,
+    pub step: u32,
+    pub steps: u32,
+    /// Completion of the current phase, when the tool reports one.
+    pub percent: Option<u8>,
+}
+
+/// Callback receiving progress updates while a render runs. It is called from
+/// the thread reading stderr, use a channel to move updates elsewhere.
+#[derive(Clone)]
+pub struct ProgressHandler(Arc<dyn Fn(&Progress) + Send + Sync>);
+
+impl ProgressHandler {
+    pub fn new(callback: impl Fn(&Progress) + Send + Sync + 'static) -> Self {
+        Self(Arc::new(callback))
+    }
+}
+
+impl fmt::Debug for ProgressHandler {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("ProgressHandler")
+    }
+}
+
+/// Collects stderr as it is read, reporting progress lines to the handler.
+pub(crate) struct StderrCollector<'a> {
+    handler: Option<&'a ProgressHandler>,
+    stderr: Vec<u8>,
+    parsed: usize,
+    current: Option<Progress>,
+}
+
+impl<'a> StderrCollector<'a> {
+    pub(crate) fn new(handler: Option<&'a ProgressHandler>) -> Self {
+        Self {
+            handler,
+            stderr: Vec::new(),
+            parsed: 0,
+            current: None,
+        }
+    }
+
+    pub(crate) fn push(&mut self, chunk: &[u8]) {
+        self.stderr.extend_from_slice(chunk);
+        if self.handler.is_none() {
+            return;
+        }
+        // Progress bars are redrawn with `\r`, so both end a line
+        while let Some(end) = self.stderr[self.parsed..]
+            .iter()
+            .position(|b| *b == b'\r' || *b == b'\n')
+        {
+            let line = String::from_utf8_lossy(&self.stderr[self.parsed..self.parsed + end]);
+            let line = line.into_owned();
+            self.parsed += end + 1;
+            self.parse_line(&line);
+        }
+    }
+
+    pub(crate) fn finish(mut self) -> Vec<u8> {
+        if self.handler.is_some() && self.parsed < self.stderr.len() {
+            let line = String::from_utf8_lossy(&self.stderr[self.parsed..]).into_owned();
+            self.parse_line(&line);
+        }
+        self.stderr
+    }
+
+    fn parse_line(&mut self, line: &str) {
+        let line = line.trim();
+        let progress = if let Some(progress) = parse_phase(line) {
+            progress
+        } else if let (Some(percent), Some(current)) = (parse_percent(line), &self.current) {
+            Progress {
+                percent: Some(percent),
+                ..current.clone()
+            }
+        } else {
+            return;
+        };
+        if let Some(handler) = self.handler {
+            (handler.0)(&progress);
+        }
+        self.current = Some(progress);
+    }
+}
+
+/// Reads `stderr` to its end, reporting progress to `handler` as it arrives.
+pub(crate) fn read_stderr(mut stderr: impl Read, handler: Option<&ProgressHandler>) -> Vec<u8> {
+    let mut collector = StderrCollector::new(handler);
+    let mut buf = [0; 8 * 1024];
+    loop {
+        match stderr.read(&mut buf) {
+            Ok(0) => break,
+            Ok(read) => collector.push(&buf[..read]),
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(_) => break,
+        }
+    }
+    collector.finish()
+}
+
+/// `Loading pages (1/6)`
+fn parse_phase(line: &str) -> Option<Progress> {
+    let (phase, count) = line.strip_suffix(')')?.rsplit_once(" (")?;
+    let (step, steps) = count.split_once('/')?;
+    Some(Progress {
+        phase: phase.to_string(),
+        step: step.parse().ok()?,
+        steps: steps.parse().ok()?,
+        percent: None,
+    })
+}
+
+/// `[=====>    ] 50%`
+fn parse_percent(line: &str) -> Option<u8> {
+    let (_, percent) = line.strip_prefix('[')?.rsplit_once(']')?;
+    percent.trim().strip_suffix('%')?.parse().ok()
+}
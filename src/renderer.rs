@@ -0,0 +1,83 @@
+use crate::app::{WkhtmlError, WkhtmlInput};
+use crate::output::{OutputData, RenderOutput};
+use std::io::Write;
+use std::path::Path;
+
+/// One object on the command line: an optional keyword such as `cover`, `toc`
+/// or `page`, its input and the options that apply to it.
+#[derive(Debug, Clone)]
+pub struct RenderObject<'a> {
+    pub keyword: Option<&'static str>,
+    pub input: Option<WkhtmlInput<'a>>,
+    pub args: Vec<String>,
+}
+
+/// Everything a `Renderer` needs to produce one output.
+#[derive(Debug, Clone)]
+pub struct RenderRequest<'a> {
+    /// Options placed before the objects.
+    pub args: Vec<String>,
+    pub objects: Vec<RenderObject<'a>>,
+    /// Argument values redacted from logs and `RenderOutput::command_line`.
+    pub secrets: Vec<String>,
+}
+
+impl<'a> RenderRequest<'a> {
+    /// A request with a single input and no object keyword.
+    pub fn new(args: Vec<String>, input: WkhtmlInput<'a>) -> Self {
+        Self {
+            args,
+            objects: vec![RenderObject {
+                keyword: None,
+                input: Some(input),
+                args: Vec::new(),
+            }],
+            secrets: Vec::new(),
+        }
+    }
+
+    pub fn with_secrets(mut self, secrets: impl IntoIterator<Item = String>) -> Self {
+        self.secrets.extend(secrets);
+        self
+    }
+
+    pub fn inputs(&self) -> impl Iterator<Item = &WkhtmlInput<'a>> {
+        self.objects
+            .iter()
+            .filter_map(|object| object.input.as_ref())
+    }
+}
+
+/// Backend that turns a `RenderRequest` into a document.
+///
+/// `Core` runs the wkhtmltopdf binaries, `MockRenderer` records requests for tests.
+pub trait Renderer {
+    /// Renders into a file called `name` and returns its path.
+    fn render_to_file(
+        &self,
+        request: RenderRequest<'_>,
+        name: &str,
+    ) -> Result<RenderOutput, WkhtmlError>;
+
+    /// Renders into `path`, which is only replaced once the render succeeded.
+    fn render_to_path(
+        &self,
+        request: RenderRequest<'_>,
+        path: &Path,
+    ) -> Result<RenderOutput, WkhtmlError>;
+
+    /// Renders into `writer` and returns the number of bytes written.
+    fn render_to_writer(
+        &self,
+        request: RenderRequest<'_>,
+        writer: &mut dyn Write,
+    ) -> Result<RenderOutput, WkhtmlError>;
+
+    fn render_to_bytes(&self, request: RenderRequest<'_>) -> Result<RenderOutput, WkhtmlError> {
+        let mut bytes = Vec::new();
+        let mut output = self.render_to_writer(request, &mut bytes)?;
+        output.data = OutputData::Bytes(bytes);
+        output.measure();
+        Ok(output)
+    }
+}
@@ -0,0 +1,94 @@
+use crate::app::{WkhtmlError, WkhtmlInput};
+use std::fs;
+use std::path::PathBuf;
+
+/// Restrictions for rendering untrusted content, set with `PdfApp::set_sandbox`
+/// or `ImgApp::set_sandbox`.
+///
+/// Local file access and plugins are always disabled, JavaScript unless
+/// `javascript` is set. These options are added after the global options and
+/// again after those of each page and cover object, so they win over them. File
+/// and URL inputs, covers included, are refused unless they match an allowlist,
+/// the default policy only renders HTML.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SandboxPolicy {
+    /// Files and directories the page may still load, passed as `--allow`.
+    pub allow_paths: Vec<PathBuf>,
+    /// Runs the page's scripts, stopping the slow ones.
+    pub javascript: bool,
+    /// Directories `WkhtmlInput::File` inputs must be in.
+    pub allowed_file_dirs: Vec<PathBuf>,
+    /// Prefixes `WkhtmlInput::Url` inputs must start with, such as
+    /// `https://example.com/reports/`.
+    pub allowed_urls: Vec<String>,
+}
+
+impl SandboxPolicy {
+    pub(crate) fn args(&self) -> Vec<String> {
+        let mut args = vec!["--disable-local-file-access".to_string()];
+        for path in &self.allow_paths {
+            args.push("--allow".to_string());
+            args.push(path.to_string_lossy().into_owned());
+        }
+        args.push("--disable-plugins".to_string());
+        if self.javascript {
+            args.push("--enable-javascript".to_string());
+            args.push("--stop-slow-scripts".to_string());
+        } else {
+            args.push("--disable-javascript".to_string());
+        }
+        args
+    }
+
+    pub(crate) fn check_input(&self, input: &WkhtmlInput) -> Result<(), WkhtmlError> {
+        match input {
+            WkhtmlInput::Html(_) => Ok(()),
+            WkhtmlInput::File(path) => self.check_file(path),
+            WkhtmlInput::Url(url) => self.check_url(url),
+        }
+    }
+
+    fn check_file(&self, path: &str) -> Result<(), WkhtmlError> {
+        // Resolves `..` and symlinks, so the path can't point out of the directories
+        let canonical = fs::canonicalize(path)
+            .map_err(|_| Self::rejected(path, "the file can't be resolved"))?;
+        let allowed = self
+            .allowed_file_dirs
+            .iter()
+            .filter_map(|dir| fs::canonicalize(dir).ok())
+            .any(|dir| canonical.starts_with(dir));
+        if allowed {
+            Ok(())
+        } else {
+            Err(Self::rejected(path, "outside of the allowed directories"))
+        }
+    }
+
+    fn check_url(&self, url: &str) -> Result<(), WkhtmlError> {
+        if self
+            .allowed_urls
+            .iter()
+            .any(|prefix| matches_prefix(url, prefix))
+        {
+            Ok(())
+        } else {
+            Err(Self::rejected(url, "not in the allowed URLs"))
+        }
+    }
+
+    fn rejected(input: &str, reason: &str) -> WkhtmlError {
+        WkhtmlError::InputNotAllowed {
+            input: input.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+}
+
+/// Whether `url` starts with `prefix` at a boundary, so that
+/// `https://example.com` does not match `https://example.com.evil.org`.
+fn matches_prefix(url: &str, prefix: &str) -> bool {
+    match url.strip_prefix(prefix) {
+        Some(rest) => prefix.ends_with('/') || rest.is_empty() || rest.starts_with(['/', '?', '#']),
+        None => false,
+    }
+}
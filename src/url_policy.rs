@@ -0,0 +1,230 @@
+use crate::app::WkhtmlError;
+use std::fmt;
+use std::net::{IpAddr, ToSocketAddrs};
+use std::str::FromStr;
+
+/// A CIDR range such as `10.0.0.0/8` or `fd00::/8`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpRange {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpRange {
+    pub fn new(addr: IpAddr, prefix: u8) -> Result<Self, WkhtmlError> {
+        if prefix > Self::bits(addr) as u8 {
+            return Err(WkhtmlError::invalid_option(
+                &format!("{}/{}", addr, prefix),
+                "the prefix is longer than the address",
+            ));
+        }
+        Ok(Self { addr, prefix })
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        // IPv4-mapped IPv6 addresses such as `::ffff:127.0.0.1` are compared as IPv4
+        let diff = match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => (u32::from(net) ^ u32::from(ip)) as u128,
+            (IpAddr::V6(net), IpAddr::V6(ip)) => u128::from(net) ^ u128::from(ip),
+            _ => return false,
+        };
+        let host_bits = Self::bits(self.addr) - self.prefix as u32;
+        diff.checked_shr(host_bits).unwrap_or(0) == 0
+    }
+
+    fn bits(addr: IpAddr) -> u32 {
+        match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        }
+    }
+}
+
+impl FromStr for IpRange {
+    type Err = WkhtmlError;
+
+    /// Parses `addr/prefix`, a bare address being a range of one.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || WkhtmlError::invalid_option(s, "not a CIDR range");
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr.parse().map_err(|_| invalid())?;
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse().map_err(|_| invalid())?,
+            None => Self::bits(addr) as u8,
+        };
+        Self::new(addr, prefix)
+    }
+}
+
+impl fmt::Display for IpRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+/// Checks `WkhtmlInput::Url` inputs, covers included, before the tool is
+/// spawned, set with `PdfApp::set_url_policy` or `ImgApp::set_url_policy`.
+///
+/// The scheme must be allowed, then the host either matches `allowed_hosts` or
+/// resolves only to addresses within `allowed_networks`. Refused URLs fail with
+/// `WkhtmlError::UrlRejected`. Names are resolved again by the tool, a DNS
+/// server answering differently the second time is not caught. Only the URL
+/// given is checked: the tool follows HTTP redirects, so an allowed host
+/// redirecting to a metadata address is not caught either.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UrlPolicy {
+    /// `http` and `https` by default.
+    pub allowed_schemes: Vec<String>,
+    /// Host names allowed whatever they resolve to, `*.example.com` matching
+    /// `example.com` and its subdomains.
+    pub allowed_hosts: Vec<String>,
+    pub allowed_networks: Vec<IpRange>,
+}
+
+impl Default for UrlPolicy {
+    fn default() -> Self {
+        Self {
+            allowed_schemes: vec!["http".to_string(), "https".to_string()],
+            allowed_hosts: Vec::new(),
+            allowed_networks: Vec::new(),
+        }
+    }
+}
+
+impl UrlPolicy {
+    pub(crate) fn check(&self, url: &str) -> Result<(), WkhtmlError> {
+        let rejected = |reason: String| WkhtmlError::UrlRejected {
+            url: url.to_string(),
+            reason,
+        };
+        let parsed = ParsedUrl::parse(url).map_err(|reason| rejected(reason.to_string()))?;
+        if !self
+            .allowed_schemes
+            .iter()
+            .any(|scheme| scheme.eq_ignore_ascii_case(&parsed.scheme))
+        {
+            return Err(rejected(format!(
+                "the {} scheme is not allowed",
+                parsed.scheme
+            )));
+        }
+        if self
+            .allowed_hosts
+            .iter()
+            .any(|pattern| host_matches(&parsed.host, pattern))
+        {
+            return Ok(());
+        }
+        if self.allowed_networks.is_empty() {
+            return Err(rejected(format!("the host {} is not allowed", parsed.host)));
+        }
+        let addrs = parsed
+            .resolve()
+            .map_err(|_| rejected(format!("the host {} can't be resolved", parsed.host)))?;
+        let allowed = !addrs.is_empty()
+            && addrs.iter().all(|ip| {
+                self.allowed_networks
+                    .iter()
+                    .any(|network| network.contains(*ip))
+            });
+        if allowed {
+            Ok(())
+        } else {
+            Err(rejected(format!(
+                "the host {} is outside of the allowed networks",
+                parsed.host
+            )))
+        }
+    }
+}
+
+/// The parts of a URL the policy looks at.
+struct ParsedUrl {
+    /// Lowercase.
+    scheme: String,
+    /// Lowercase, without the brackets of an IPv6 address.
+    host: String,
+    port: Option<u16>,
+}
+
+impl ParsedUrl {
+    fn parse(url: &str) -> Result<Self, &'static str> {
+        if url.chars().any(|c| c.is_whitespace() || c.is_control()) {
+            return Err("the URL contains whitespace");
+        }
+        let (scheme, rest) = url.split_once(':').ok_or("not an absolute URL")?;
+        let valid_scheme = scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+            && scheme
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'));
+        if !valid_scheme {
+            return Err("not an absolute URL");
+        }
+        let rest = rest.strip_prefix("//").ok_or("the URL has no host")?;
+        let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+        // Parsers disagree on anything else, a backslash ends the host for browsers
+        // but is part of the user info for the tool
+        let valid_authority = authority
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-._~%!$&'()*+,;=:@[]".contains(c))
+            && authority.matches('@').count() <= 1;
+        if !valid_authority {
+            return Err("the host part contains invalid characters");
+        }
+        let host_port = match authority.rsplit_once('@') {
+            Some((_, host_port)) => host_port,
+            None => authority,
+        };
+        let (host, port) = match host_port.strip_prefix('[') {
+            Some(bracketed) => {
+                let (host, after) = bracketed.split_once(']').ok_or("invalid IPv6 host")?;
+                (host, after.strip_prefix(':'))
+            }
+            None => match host_port.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (host_port, None),
+            },
+        };
+        // Percent-encoded hosts are decoded by the tool, not by this check
+        if host.is_empty() || host.contains('%') {
+            return Err("the URL has no valid host");
+        }
+        let port = match port {
+            Some("") | None => None,
+            Some(port) => Some(port.parse().map_err(|_| "invalid port")?),
+        };
+        Ok(Self {
+            scheme: scheme.to_ascii_lowercase(),
+            host: host.trim_end_matches('.').to_ascii_lowercase(),
+            port,
+        })
+    }
+
+    fn resolve(&self) -> std::io::Result<Vec<IpAddr>> {
+        if let Ok(ip) = self.host.parse() {
+            return Ok(vec![ip]);
+        }
+        let port = self.port.unwrap_or(match self.scheme.as_str() {
+            "https" => 443,
+            _ => 80,
+        });
+        let addrs = (self.host.as_str(), port).to_socket_addrs()?;
+        Ok(addrs.map(|addr| addr.ip()).collect())
+    }
+}
+
+fn host_matches(host: &str, pattern: &str) -> bool {
+    let pattern = pattern.trim_end_matches('.').to_ascii_lowercase();
+    match pattern.strip_prefix("*.") {
+        Some(domain) => {
+            host == domain
+                || host
+                    .strip_suffix(domain)
+                    .is_some_and(|sub| sub.ends_with('.'))
+        }
+        None => host == pattern,
+    }
+}
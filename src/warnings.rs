@@ -0,0 +1,77 @@
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WarningKind {
+    /// A resource of the page (image, stylesheet, script, ...) failed to load.
+    ResourceLoadFailed,
+    /// The page itself failed to load.
+    PageLoadFailed,
+    /// Any other `Warning:` line.
+    Warning,
+    /// Any other `Error:` line.
+    Error,
+}
+
+/// A `Warning:` or `Error:` line printed by wkhtmltopdf on stderr.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RenderWarning {
+    pub kind: WarningKind,
+    /// The URL that failed to load, if the line names one.
+    pub url: Option<String>,
+    /// The line without its `Warning:` / `Error:` prefix.
+    pub message: String,
+}
+
+impl RenderWarning {
+    pub fn is_error(&self) -> bool {
+        matches!(self.kind, WarningKind::PageLoadFailed | WarningKind::Error)
+    }
+
+    fn parse(line: &str) -> Option<Self> {
+        let line = line.trim();
+        let (error, message) = if let Some(message) = line.strip_prefix("Warning:") {
+            (false, message.trim())
+        } else if let Some(message) = line.strip_prefix("Error:") {
+            (true, message.trim())
+        } else {
+            return None;
+        };
+
+        let (kind, url) = if let Some(rest) = message.strip_prefix("Failed loading page ") {
+            (WarningKind::PageLoadFailed, url_of(rest))
+        } else if let Some(rest) = message.strip_prefix("Failed to load ") {
+            (WarningKind::ResourceLoadFailed, url_of(rest))
+        } else if error {
+            (WarningKind::Error, None)
+        } else {
+            (WarningKind::Warning, None)
+        };
+        Some(Self {
+            kind,
+            url,
+            message: message.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for RenderWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+/// Extracts the warnings and errors from the stderr of a render, skipping the
+/// progress output.
+pub fn parse_warnings(stderr: &str) -> Vec<RenderWarning> {
+    // Progress bars are redrawn with `\r`, so a warning can follow one on the same line
+    stderr
+        .split(['\r', '\n'])
+        .filter_map(RenderWarning::parse)
+        .collect()
+}
+
+fn url_of(rest: &str) -> Option<String> {
+    // "Failed to load about:blank, with network status code 301 ..."
+    let url = rest.split_whitespace().next()?.trim_end_matches(',');
+    Some(url.to_string())
+}
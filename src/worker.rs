@@ -0,0 +1,295 @@
+use crate::app::{WkhtmlError, WkhtmlInput};
+use crate::core::{args_line, kill_tree, redact, Core, READ_ARGS_FROM_STDIN};
+use crate::options::LogLevel;
+use crate::output::{OutputData, OutputPath, RenderOutput};
+use crate::pdf::PdfApp;
+use crate::pdf_options::PdfOptions;
+use crate::progress::StderrCollector;
+use crate::warnings::parse_warnings;
+use log::{debug, error, info, warn};
+use std::io::{BufWriter, ErrorKind, Read, Write};
+use std::process::{Child, ChildStdin, Stdio};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::Instant;
+
+/// Renders documents one after the other with a single wkhtmltopdf process
+/// started with `--read-args-from-stdin`, which saves the QtWebKit startup on
+/// every document.
+///
+/// Each job is written as one command line; the tool reports its end on
+/// stderr with `Done` or `Exit with code N`, after which it exits. The process
+/// is started on the first render and started again after a failed job, a crash
+/// or a timeout. Jobs run in
+/// turn, wrap the worker in a `Mutex` to share it, or keep one worker per thread.
+#[derive(Debug)]
+pub struct PersistentWorker {
+    core: Core,
+    options: PdfOptions,
+    process: Option<WorkerProcess>,
+    restarts: u64,
+}
+
+#[derive(Debug)]
+struct WorkerProcess {
+    child: Child,
+    stdin: BufWriter<ChildStdin>,
+    /// Stderr lines, closed when the process exits.
+    lines: mpsc::Receiver<String>,
+}
+
+/// How the current job ended.
+enum JobEnd {
+    Done,
+    Failed(Option<i32>),
+    Crashed(Option<i32>),
+    TimedOut,
+}
+
+impl PersistentWorker {
+    /// A worker rendering with the command, work dir, timeout and options of `app`.
+    ///
+    /// `quiet` and `log-level` are overridden, the tool has to log at the info
+    /// level for the end of each job to be seen.
+    pub fn new(app: &PdfApp) -> Self {
+        let mut options = app.options.clone();
+        options.quiet = false;
+        options.log_level = Some(LogLevel::Info);
+        options.extra.retain(|option| {
+            !matches!(
+                option.key.as_str(),
+                "quiet" | "log-level" | READ_ARGS_FROM_STDIN
+            )
+        });
+        Self {
+            core: app.app.clone(),
+            options,
+            process: None,
+            restarts: 0,
+        }
+    }
+
+    /// Number of times the process was started again after a failed job, a crash
+    /// or a timeout.
+    pub fn restarts(&self) -> u64 {
+        self.restarts
+    }
+
+    /// Renders `input` to `<name>.pdf` in the work dir. HTML is written to a
+    /// temporary file, as stdin carries the jobs.
+    pub fn run(&mut self, input: WkhtmlInput, name: &str) -> Result<RenderOutput, WkhtmlError> {
+        Core::check_name(name)?;
+        let request = self.options.to_request(input);
+        // Stdin carries the jobs, HTML inputs go through temporary files
+        let mut temp_files = Vec::new();
+        let built = self
+            .core
+            .build_command_line(&request, &mut temp_files, true);
+        let _temp_inputs: Vec<OutputPath> = temp_files.into_iter().map(OutputPath::new).collect();
+        let mut args = built?.0;
+        // Removes partial output if the render fails
+        let out_path = OutputPath::new(self.core.get_out_path(&format!("{}.pdf", name)));
+        args.push(out_path.to_string_lossy().into_owned());
+        let line = args_line(&args)?;
+
+        let started = Instant::now();
+        let progress = self.core.progress.clone();
+        let mut stderr = StderrCollector::new(progress.as_ref());
+        let end = self.send(&line, &mut stderr)?;
+        let stderr = stderr.finish();
+        let code = match end {
+            JobEnd::Done => None,
+            JobEnd::Failed(code) => {
+                // The tool exits after a failed conversion, it is not waited for
+                self.stop();
+                self.restarts += 1;
+                code
+            }
+            JobEnd::Crashed(code) => {
+                warn!("Persistent worker exited during a job, it will be restarted");
+                self.restarts += 1;
+                code
+            }
+            JobEnd::TimedOut => {
+                error!(
+                    "Persistent worker job exceeded its timeout of {:?}, killing the worker",
+                    self.core.timeout.unwrap_or_default()
+                );
+                self.stop();
+                self.restarts += 1;
+                return Err(self.core.timeout_err(&stderr));
+            }
+        };
+        let warnings = parse_warnings(&String::from_utf8_lossy(&stderr));
+        for warning in &warnings {
+            warn!("{}", warning);
+        }
+        if !matches!(end, JobEnd::Done) {
+            return Err(WkhtmlError::NonZeroExit {
+                code,
+                stderr: String::from_utf8_lossy(&stderr).into_owned(),
+                warnings,
+            });
+        }
+
+        let mut command_line = vec![self.core.wkhtmltox_cmd.clone()];
+        command_line.extend(redact(&args, &self.options.secrets()));
+        let mut output = RenderOutput {
+            data: OutputData::File(out_path),
+            size: 0,
+            duration: started.elapsed(),
+            exit_code: Some(0),
+            warnings,
+            command_line,
+            page_count: None,
+        };
+        output.measure();
+        Ok(output)
+    }
+
+    /// Closes the process' stdin, letting it exit once the current job is done.
+    pub fn shutdown(mut self) {
+        self.close();
+    }
+
+    /// Writes `line` to the process and waits for the end of the job.
+    fn send(&mut self, line: &str, stderr: &mut StderrCollector) -> Result<JobEnd, WkhtmlError> {
+        self.ensure_started()?;
+        if self.write(line).is_err() {
+            // The process died while idle, it is only noticed now
+            debug!("Persistent worker is gone, restarting it");
+            self.stop();
+            self.restarts += 1;
+            self.ensure_started()?;
+            self.write(line)
+                .map_err(|e| WkhtmlError::io("Failed to send job to worker", e))?;
+        }
+        let process = self.process.as_mut().expect("worker started");
+        let deadline = self.core.timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            let received = match deadline {
+                Some(deadline) => process
+                    .lines
+                    .recv_timeout(deadline.saturating_duration_since(Instant::now())),
+                None => process
+                    .lines
+                    .recv()
+                    .map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            let line = match received {
+                Ok(line) => line,
+                Err(RecvTimeoutError::Timeout) => return Ok(JobEnd::TimedOut),
+                Err(RecvTimeoutError::Disconnected) => {
+                    // Reaped here, so `stop` never signals a PID that may be reused
+                    let mut process = self.process.take().expect("worker started");
+                    let status = process.child.wait().ok();
+                    return Ok(JobEnd::Crashed(status.and_then(|status| status.code())));
+                }
+            };
+            let trimmed = line.trim();
+            if trimmed == "Done" {
+                return Ok(JobEnd::Done);
+            }
+            stderr.push(line.as_bytes());
+            stderr.push(b"\n");
+            if let Some(code) = trimmed.strip_prefix("Exit with code") {
+                let code = code
+                    .trim_start()
+                    .split(|c: char| !c.is_ascii_digit())
+                    .next();
+                return Ok(JobEnd::Failed(code.and_then(|code| code.parse().ok())));
+            }
+        }
+    }
+
+    fn ensure_started(&mut self) -> Result<(), WkhtmlError> {
+        if self.process.is_none() {
+            self.process = Some(self.start()?);
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, line: &str) -> Result<(), std::io::Error> {
+        let process = self.process.as_mut().expect("worker started");
+        writeln!(process.stdin, "{}", line)?;
+        process.stdin.flush()
+    }
+
+    fn start(&self) -> Result<WorkerProcess, WkhtmlError> {
+        let mut command = self
+            .core
+            .command(vec![format!("--{}", READ_ARGS_FROM_STDIN)], true);
+        // Outputs always go to files, nothing is read from stdout
+        command.stdout(Stdio::null());
+        let mut child = command
+            .spawn()
+            .map_err(|e| Core::spawn_err(&self.core.wkhtmltox_cmd, e))?;
+        info!("Started persistent worker {}", child.id());
+        let (Some(stdin), Some(stderr)) = (child.stdin.take(), child.stderr.take()) else {
+            return Err(WkhtmlError::io(
+                "Failed to open worker pipes",
+                ErrorKind::BrokenPipe.into(),
+            ));
+        };
+        let (sender, lines) = mpsc::channel();
+        thread::spawn(move || read_lines(stderr, sender));
+        Ok(WorkerProcess {
+            child,
+            stdin: BufWriter::new(stdin),
+            lines,
+        })
+    }
+
+    /// Kills the process, the next job starts a new one.
+    fn stop(&mut self) {
+        if let Some(mut process) = self.process.take() {
+            // On unix the process only leads its own group with a timeout, see `Core::command`
+            if !cfg!(unix) || self.core.timeout.is_some() {
+                kill_tree(process.child.id());
+            }
+            let _ = process.child.kill();
+            let _ = process.child.wait();
+        }
+    }
+
+    fn close(&mut self) {
+        if let Some(mut process) = self.process.take() {
+            drop(process.stdin);
+            let _ = process.child.wait();
+        }
+    }
+}
+
+impl Drop for PersistentWorker {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+/// Sends every stderr line, progress bars being redrawn with `\r`.
+fn read_lines(mut stderr: impl Read, sender: mpsc::Sender<String>) {
+    let mut pending = Vec::new();
+    let mut buf = [0; 8 * 1024];
+    loop {
+        let read = match stderr.read(&mut buf) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(_) => break,
+        };
+        for byte in &buf[..read] {
+            if *byte == b'\r' || *byte == b'\n' {
+                let line = String::from_utf8_lossy(&pending).into_owned();
+                pending.clear();
+                if !line.is_empty() && sender.send(line).is_err() {
+                    return;
+                }
+            } else {
+                pending.push(*byte);
+            }
+        }
+    }
+    if !pending.is_empty() {
+        let _ = sender.send(String::from_utf8_lossy(&pending).into_owned());
+    }
+}